@@ -1,21 +1,45 @@
-#[cfg(not(target_os = "macos"))]
+//! Normal builds use the hand-maintained bindings checked in at `src/sys/bindings.rs`. With the
+//! `regenerate-bindings` feature they are overwritten with bindgen's output for `notify.h` and the
+//! installed macOS SDK, to compare with before porting the changes by hand.
+
 fn main() {
-    panic!("Cannot build for non OSX platforms")
+    #[cfg(feature = "regenerate-bindings")]
+    regenerate();
 }
 
-#[cfg(target_os = "macos")]
-fn main() {
-    use std::env;
+#[cfg(feature = "regenerate-bindings")]
+fn regenerate() {
     use std::path::PathBuf;
 
+    println!("cargo:rerun-if-changed=notify.h");
+
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("macos") {
+        println!("cargo:warning=regenerate-bindings needs the macOS SDK, keeping the checked-in bindings");
+        return;
+    }
+
     let bindings = bindgen::Builder::default()
         .header("notify.h")
-        .parse_callbacks(Box::new(bindgen::CargoCallbacks))
+        .raw_line("// Generated by bindgen from notify.h with `--features regenerate-bindings`.")
+        .raw_line("// Port the changes to the hand-maintained bindings, see the README.")
+        .allowlist_function("notify_.*")
+        .allowlist_function("dispatch_(get_current_queue|get_global_queue)")
+        .allowlist_function(
+            "dispatch_(queue_create|queue_attr_make_with_qos_class|release|async_f)",
+        )
+        .allowlist_function("CFRunLoop(Run|GetCurrent|RunInMode|Stop|WakeUp)")
+        .allowlist_var("NOTIFY_(STATUS_.*|REUSE|TOKEN_INVALID)")
+        .allowlist_var("QOS_CLASS_.*")
+        .allowlist_var("_dispatch_main_q")
+        .allowlist_var("kCFRunLoop(Run.*|DefaultMode)")
+        .layout_tests(false)
+        .merge_extern_blocks(true)
         .generate()
-        .expect("Unable to generate bindings for endpoint security");
+        .expect("Unable to generate bindings for notify.h");
 
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let path =
+        PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("src/sys/bindings.rs");
     bindings
-        .write_to_file(out_path.join("notify_sys.rs"))
+        .write_to_file(path)
         .expect("Couldn't write bindings!");
 }
@@ -1,5 +1,19 @@
 #include <notify.h>
 
-dispatch_queue_main_t dispatch_get_main_queue(void);
+typedef struct __CFRunLoop * CFRunLoopRef;
+typedef const struct __CFString * CFStringRef;
+typedef double CFTimeInterval;
+typedef unsigned char Boolean;
 
-void CFRunLoopRun(void);
\ No newline at end of file
+#define kCFRunLoopRunFinished 1
+#define kCFRunLoopRunStopped 2
+#define kCFRunLoopRunTimedOut 3
+#define kCFRunLoopRunHandledSource 4
+
+extern const CFStringRef kCFRunLoopDefaultMode;
+
+void CFRunLoopRun(void);
+CFRunLoopRef CFRunLoopGetCurrent(void);
+int CFRunLoopRunInMode(CFStringRef mode, CFTimeInterval seconds, Boolean returnAfterSourceHandled);
+void CFRunLoopStop(CFRunLoopRef rl);
+void CFRunLoopWakeUp(CFRunLoopRef rl);
@@ -0,0 +1,735 @@
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, RwLock};
+use std::time::Duration;
+
+use super::{Callback, CheckPoller, NotifyBackend, StateCallback};
+use crate::proto::{self, Message, Reply, Request};
+use crate::retry::{self, Resubscribed};
+use crate::shm::{self, Shm};
+use crate::signal;
+use crate::{notifyd, NResult, NotifyError};
+
+/// Wait before the first attempt to reconnect, doubled after every failed attempt.
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(50);
+/// Longest wait between two attempts to reconnect.
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Backend talking to a [notifyd](crate::notifyd) server over a Unix domain socket.
+///
+/// Callbacks run one at a time on a dispatcher thread owned by the backend. Check tokens are
+/// polled from the generation page the server shares next to its socket when it is available.
+///
+/// When the server goes away, requests fail with [NotifyError::ServerNotFound] and the backend
+/// keeps trying to reconnect while tokens are registered. Once it is back every live token is
+/// registered again, keeping its value, suspended as many times as before, and the
+/// [resubscribe hook](crate::set_resubscribe_hook) is called. States start over with the new
+/// server.
+pub struct DaemonBackend {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    path: PathBuf,
+    /// `None` while the server is away.
+    conn: Mutex<Option<Conn>>,
+    next_conn: AtomicU64,
+    pending: Mutex<HashMap<u32, Pending>>,
+    next_seq: AtomicU32,
+    tokens: Mutex<Tokens>,
+    checks: RwLock<HashMap<c_int, Arc<Check>>>,
+    events: Mutex<mpsc::Sender<Event>>,
+    /// Set once the backend is dropped, stops reconnecting.
+    closed: AtomicBool,
+}
+
+struct Conn {
+    id: u64,
+    stream: UnixStream,
+}
+
+/// The tokens handed out, which stay the same across connections.
+#[derive(Default)]
+struct Tokens {
+    next: c_int,
+    live: HashMap<c_int, Registration>,
+    /// Token of each server token of the current connection.
+    by_server: HashMap<c_int, c_int>,
+}
+
+struct Registration {
+    name: String,
+    kind: Kind,
+    /// Token the current connection knows the registration by.
+    server: Option<c_int>,
+    suspended: u32,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Deliver,
+    State,
+    Check,
+}
+
+/// A check token, polled from a counter of the generation page when the server gave it one.
+///
+/// Shared with the [Subscription](crate::Subscription) of the token, so polling it takes no lock.
+#[derive(Default)]
+struct Check {
+    /// Counter in the page of the current connection, null when checks go to the server.
+    counter: AtomicPtr<AtomicU64>,
+    last: AtomicU64,
+    /// Every page `counter` pointed into, mapped for as long as it may still be read.
+    pages: Mutex<Vec<Arc<Shm>>>,
+}
+
+/// A request waiting for its reply.
+struct Pending {
+    conn: u64,
+    tx: mpsc::Sender<Reply>,
+    kind: PendingKind,
+}
+
+enum PendingKind {
+    Register {
+        token: c_int,
+        name: String,
+        kind: Kind,
+        handler: Option<Handler>,
+    },
+    Resubscribe(c_int, Kind),
+    Cancel(c_int),
+    Other,
+}
+
+/// What runs when a token is delivered.
+enum Handler {
+    Token(Callback),
+    State(StateCallback),
+}
+
+/// Work for the dispatcher thread, which owns the callbacks.
+enum Event {
+    Registered(c_int, Handler),
+    Cancelled(c_int),
+    Deliver(c_int),
+    DeliverState(c_int, u64),
+}
+
+impl DaemonBackend {
+    /// Connect to the server listening on `path`.
+    ///
+    /// Fails with [NotifyError::ServerNotFound] when nothing listens on `path`, and with
+    /// [NotifyError::NotAuthorized] when the socket doesn't let this user in.
+    pub fn connect(path: impl AsRef<Path>) -> NResult<Self> {
+        let backend = Self::new(path);
+        backend.shared.connect(&mut backend.shared.lock_conn())?;
+        Ok(backend)
+    }
+
+    /// Connect to the server at [notifyd::socket_path].
+    pub fn connect_default() -> NResult<Self> {
+        Self::connect(notifyd::socket_path())
+    }
+
+    /// Backend for the server listening on `path`, connecting on first use.
+    ///
+    /// Requests fail with [NotifyError::ServerNotFound] until the server is up, see
+    /// [RetryPolicy](crate::RetryPolicy) to wait for it.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        let (events, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            signal::block_all();
+            dispatch(rx)
+        });
+
+        Self {
+            shared: Arc::new(Shared {
+                path: path.as_ref().to_owned(),
+                conn: Mutex::new(None),
+                next_conn: AtomicU64::new(0),
+                pending: Default::default(),
+                next_seq: AtomicU32::new(0),
+                tokens: Default::default(),
+                checks: Default::default(),
+                events: Mutex::new(events),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    fn register_kind(&self, name: &str, kind: Kind, handler: Option<Handler>) -> NResult<c_int> {
+        let token = self.shared.next_token();
+        self.shared.call(
+            kind.request(name),
+            PendingKind::Register {
+                token,
+                name: name.into(),
+                kind,
+                handler,
+            },
+        )?;
+
+        Ok(token)
+    }
+}
+
+impl Shared {
+    fn lock_conn(&self) -> MutexGuard<'_, Option<Conn>> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_pending(&self) -> MutexGuard<'_, HashMap<u32, Pending>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_tokens(&self) -> MutexGuard<'_, Tokens> {
+        self.tokens.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn event(&self, event: Event) {
+        _ = self
+            .events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(event);
+    }
+
+    fn next_token(&self) -> c_int {
+        let mut tokens = self.lock_tokens();
+        tokens.next += 1;
+        tokens.next
+    }
+
+    fn call(self: &Arc<Self>, req: Request, kind: PendingKind) -> NResult<u64> {
+        self.call_with(|_| Ok(req), kind)
+    }
+
+    /// Call with a request for the server token of `token`.
+    fn call_token(
+        self: &Arc<Self>,
+        token: c_int,
+        req: impl FnOnce(c_int) -> Request,
+        kind: PendingKind,
+    ) -> NResult<u64> {
+        self.call_with(
+            |shared| match shared.lock_tokens().live.get(&token) {
+                Some(reg) => reg.server.map(req).ok_or(NotifyError::ServerNotFound),
+                None => Err(NotifyError::InvalidToken),
+            },
+            kind,
+        )
+    }
+
+    /// Send the request made by `req`, connecting first if needed.
+    ///
+    /// The request is made while holding the connection, so server tokens can't change under it.
+    fn call_with(
+        self: &Arc<Self>,
+        req: impl FnOnce(&Self) -> NResult<Request>,
+        kind: PendingKind,
+    ) -> NResult<u64> {
+        let mut conn = self.lock_conn();
+
+        if conn.is_none() {
+            let resubscribed = self.connect(&mut conn)?;
+
+            if let Some(resubscribed) = resubscribed {
+                // The hook may use the backend.
+                drop(conn);
+                retry::resubscribed(&resubscribed);
+                conn = self.lock_conn();
+            }
+        }
+
+        let rx = {
+            let conn = conn.as_ref().ok_or(NotifyError::ServerNotFound)?;
+            self.send(conn, req(self)?, kind)?
+        };
+        drop(conn);
+
+        Self::wait(rx)
+    }
+
+    fn send(&self, conn: &Conn, req: Request, kind: PendingKind) -> NResult<mpsc::Receiver<Reply>> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        self.lock_pending().insert(
+            seq,
+            Pending {
+                conn: conn.id,
+                tx,
+                kind,
+            },
+        );
+
+        if proto::write_frame(&mut &conn.stream, &req.encode(seq)).is_err() {
+            self.lock_pending().remove(&seq);
+            return Err(NotifyError::ServerNotFound);
+        }
+
+        Ok(rx)
+    }
+
+    fn wait(rx: mpsc::Receiver<Reply>) -> NResult<u64> {
+        match rx.recv() {
+            Ok(Reply {
+                status: 0, value, ..
+            }) => Ok(value),
+            Ok(Reply { status, .. }) => Err(NotifyError::from_u32(status)),
+            Err(_) => Err(NotifyError::ServerNotFound),
+        }
+    }
+
+    /// Connect to the server and register the live tokens again, returning what was registered
+    /// when there was any.
+    ///
+    /// On failure the connection may already be set, its reader tears it down.
+    fn connect(self: &Arc<Self>, conn: &mut Option<Conn>) -> NResult<Option<Resubscribed>> {
+        let stream = UnixStream::connect(&self.path).map_err(connect_error)?;
+        let reader = stream.try_clone().map_err(|_| NotifyError::Failed)?;
+        // Opened again on every connection, the server creates a new page when it starts.
+        let shm = Shm::open(&notifyd::shm_path(&self.path)).ok().map(Arc::new);
+
+        let id = self.next_conn.fetch_add(1, Ordering::Relaxed);
+        let shared = self.clone();
+        std::thread::spawn(move || {
+            signal::block_all();
+            shared.read(id, reader, shm)
+        });
+
+        let conn = conn.insert(Conn { id, stream });
+        self.resubscribe(conn)
+    }
+
+    fn resubscribe(&self, conn: &Conn) -> NResult<Option<Resubscribed>> {
+        let live: Vec<_> = self
+            .lock_tokens()
+            .live
+            .iter()
+            .map(|(token, reg)| (*token, reg.name.clone(), reg.kind, reg.suspended))
+            .collect();
+
+        if live.is_empty() {
+            return Ok(None);
+        }
+
+        let mut resubscribed = Resubscribed {
+            path: self.path.clone(),
+            tokens: Vec::new(),
+            failed: Vec::new(),
+        };
+
+        for (token, name, kind, suspended) in live {
+            let register = || -> NResult<()> {
+                let rx = self.send(
+                    conn,
+                    kind.request(&name),
+                    PendingKind::Resubscribe(token, kind),
+                )?;
+                let server = kind.server_token(Self::wait(rx)?);
+
+                for _ in 0..suspended {
+                    Self::wait(self.send(conn, Request::Suspend(server), PendingKind::Other)?)?;
+                }
+                Ok(())
+            };
+
+            match register() {
+                Ok(()) => resubscribed.tokens.push(token),
+                Err(err) if err.is_retryable() => return Err(err),
+                Err(_) => {
+                    self.forget(token);
+                    resubscribed.failed.push(token);
+                }
+            }
+        }
+
+        Ok(Some(resubscribed))
+    }
+
+    /// Read the messages of connection `id` until it closes, then reconnect.
+    fn read(self: Arc<Self>, id: u64, mut reader: UnixStream, shm: Option<Arc<Shm>>) {
+        while let Ok(frame) = proto::read_frame(&mut reader) {
+            match Message::decode(&frame) {
+                Ok(Message::Reply(reply)) => self.complete(reply, shm.as_ref()),
+                Ok(Message::Deliver(server)) => {
+                    if let Some(token) = self.token(server) {
+                        self.event(Event::Deliver(token))
+                    }
+                }
+                Ok(Message::DeliverState(server, state)) => {
+                    if let Some(token) = self.token(server) {
+                        self.event(Event::DeliverState(token, state))
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+
+        self.disconnected(id);
+        self.reconnect();
+    }
+
+    /// The token handed out for `server`.
+    fn token(&self, server: c_int) -> Option<c_int> {
+        self.lock_tokens().by_server.get(&server).copied()
+    }
+
+    fn complete(&self, reply: Reply, shm: Option<&Arc<Shm>>) {
+        let Some(pending) = self.lock_pending().remove(&reply.seq) else {
+            return;
+        };
+
+        // Map the token before waking the caller, deliveries for it may follow.
+        if reply.status == 0 {
+            match pending.kind {
+                PendingKind::Register {
+                    token,
+                    name,
+                    kind,
+                    handler,
+                } => {
+                    let server = kind.server_token(reply.value);
+                    let mut tokens = self.lock_tokens();
+                    tokens.by_server.insert(server, token);
+                    tokens.live.insert(
+                        token,
+                        Registration {
+                            name,
+                            kind,
+                            server: Some(server),
+                            suspended: 0,
+                        },
+                    );
+                    drop(tokens);
+
+                    self.track_check(token, kind, reply.value, shm);
+                    if let Some(handler) = handler {
+                        self.event(Event::Registered(token, handler))
+                    }
+                }
+                PendingKind::Resubscribe(token, kind) => {
+                    let server = kind.server_token(reply.value);
+                    let mut tokens = self.lock_tokens();
+                    if let Some(reg) = tokens.live.get_mut(&token) {
+                        reg.server = Some(server);
+                        tokens.by_server.insert(server, token);
+                    }
+                    drop(tokens);
+
+                    self.track_check(token, kind, reply.value, shm);
+                }
+                PendingKind::Cancel(token) => self.forget(token),
+                PendingKind::Other => {}
+            }
+        }
+
+        _ = pending.tx.send(reply);
+    }
+
+    /// Poll check `token` from the generation page when the server gave it a counter.
+    fn track_check(&self, token: c_int, kind: Kind, value: u64, shm: Option<&Arc<Shm>>) {
+        if !matches!(kind, Kind::Check) {
+            return;
+        }
+
+        let (_, slot) = proto::unpack_check(value);
+        let shm = shm.filter(|_| slot != shm::NO_SLOT);
+
+        // Kept across connections, the subscription holding it follows the new counter.
+        self.checks
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(token)
+            .or_default()
+            .remap(shm.map(|shm| (shm, slot)));
+    }
+
+    /// Drop everything about `token`, which the server no longer knows.
+    fn forget(&self, token: c_int) {
+        let mut tokens = self.lock_tokens();
+        if let Some(server) = tokens.live.remove(&token).and_then(|reg| reg.server) {
+            tokens.by_server.remove(&server);
+        }
+        drop(tokens);
+
+        self.checks
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&token);
+        self.event(Event::Cancelled(token));
+    }
+
+    /// Tear down connection `id` after it closed.
+    fn disconnected(&self, id: u64) {
+        // Dropping the senders fails every request still waiting for a reply, including a
+        // resubscription holding the connection.
+        self.lock_pending().retain(|_, pending| pending.conn != id);
+
+        let mut conn = self.lock_conn();
+        if conn.as_ref().map(|conn| conn.id) != Some(id) {
+            return;
+        }
+        *conn = None;
+
+        let mut tokens = self.lock_tokens();
+        tokens.by_server.clear();
+        for reg in tokens.live.values_mut() {
+            reg.server = None;
+        }
+    }
+
+    /// Reconnect in the background while tokens are registered, until the backend is dropped.
+    fn reconnect(self: &Arc<Self>) {
+        let mut backoff = RECONNECT_BACKOFF;
+
+        while !self.closed.load(Ordering::Relaxed) && !self.lock_tokens().live.is_empty() {
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+
+            let mut conn = self.lock_conn();
+            if conn.is_some() || self.closed.load(Ordering::Relaxed) {
+                return;
+            }
+
+            match self.connect(&mut conn) {
+                Ok(resubscribed) => {
+                    drop(conn);
+                    if let Some(resubscribed) = resubscribed {
+                        retry::resubscribed(&resubscribed);
+                    }
+                    return;
+                }
+                // Lost again, the reader of the new connection takes over.
+                Err(_) if conn.is_some() => return,
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+/// Only a missing or refusing socket means the server is away, anything else won't go away by
+/// retrying.
+fn connect_error(err: io::Error) -> NotifyError {
+    match err.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => NotifyError::ServerNotFound,
+        io::ErrorKind::PermissionDenied => NotifyError::NotAuthorized,
+        _ => NotifyError::Failed,
+    }
+}
+
+impl Check {
+    /// Poll `slot` of `shm` from now on, or the server without one.
+    fn remap(&self, counter: Option<(&Arc<Shm>, u32)>) {
+        let Some((shm, slot)) = counter else {
+            self.counter.store(ptr::null_mut(), Ordering::Release);
+            return;
+        };
+
+        let mut pages = self.pages.lock().unwrap_or_else(|e| e.into_inner());
+        if !pages.last().is_some_and(|page| Arc::ptr_eq(page, shm)) {
+            pages.push(shm.clone());
+        }
+
+        // One behind the current generation, the first check reports a post like on Darwin.
+        let counter = shm.slot(slot);
+        self.last.store(
+            counter.load(Ordering::Acquire).wrapping_sub(1),
+            Ordering::Relaxed,
+        );
+        self.counter
+            .store(ptr::from_ref(counter).cast_mut(), Ordering::Release);
+    }
+}
+
+impl CheckPoller for Check {
+    fn poll(&self) -> Option<bool> {
+        let counter = self.counter.load(Ordering::Acquire);
+
+        // Points into one of the pages, which live as long as self.
+        let generation = unsafe { counter.as_ref()? }.load(Ordering::Acquire);
+        Some(self.last.swap(generation, Ordering::Relaxed) != generation)
+    }
+}
+
+impl Kind {
+    fn request(self, name: &str) -> Request {
+        match self {
+            Kind::Deliver => Request::Register(name.into()),
+            Kind::State => Request::RegisterState(name.into()),
+            Kind::Check => Request::RegisterCheck(name.into()),
+        }
+    }
+
+    /// The server token in the reply to a registration.
+    fn server_token(self, value: u64) -> c_int {
+        match self {
+            Kind::Check => proto::unpack_check(value).0,
+            Kind::Deliver | Kind::State => value as c_int,
+        }
+    }
+}
+
+fn dispatch(rx: mpsc::Receiver<Event>) {
+    let mut callbacks = HashMap::new();
+
+    for event in rx {
+        match event {
+            Event::Registered(token, handler) => _ = callbacks.insert(token, handler),
+            Event::Cancelled(token) => _ = callbacks.remove(&token),
+            Event::Deliver(token) => {
+                if let Some(Handler::Token(cb)) = callbacks.get(&token) {
+                    cb(token)
+                }
+            }
+            Event::DeliverState(token, state) => {
+                if let Some(Handler::State(cb)) = callbacks.get(&token) {
+                    cb(token, state)
+                }
+            }
+        }
+    }
+}
+
+impl Drop for DaemonBackend {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+
+        if let Some(conn) = self.shared.lock_conn().as_ref() {
+            _ = conn.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl NotifyBackend for DaemonBackend {
+    fn post(&self, name: &str) -> NResult<()> {
+        self.shared
+            .call(Request::Post(name.into()), PendingKind::Other)
+            .map(drop)
+    }
+
+    fn post_with_state(&self, name: &str, state: u64) -> NResult<()> {
+        self.shared
+            .call(Request::PostState(name.into(), state), PendingKind::Other)
+            .map(drop)
+    }
+
+    fn state_compare_exchange(
+        &self,
+        name: &str,
+        expected: u64,
+        new: u64,
+        post: bool,
+    ) -> NResult<Result<u64, u64>> {
+        let prev = self.shared.call(
+            Request::StateCompareExchange(name.into(), expected, new, post),
+            PendingKind::Other,
+        )?;
+
+        Ok(if prev == expected {
+            Ok(prev)
+        } else {
+            Err(prev)
+        })
+    }
+
+    fn state_fetch_add(&self, name: &str, delta: u64, post: bool) -> NResult<u64> {
+        self.shared.call(
+            Request::StateFetchAdd(name.into(), delta, post),
+            PendingKind::Other,
+        )
+    }
+
+    fn register(&self, name: &str, cb: Callback) -> NResult<c_int> {
+        self.register_kind(name, Kind::Deliver, Some(Handler::Token(cb)))
+    }
+
+    /// The server captures the state when the name is posted.
+    fn register_state(self: Arc<Self>, name: &str, cb: StateCallback) -> NResult<c_int> {
+        self.register_kind(name, Kind::State, Some(Handler::State(cb)))
+    }
+
+    fn register_check(&self, name: &str) -> NResult<c_int> {
+        self.register_kind(name, Kind::Check, None)
+    }
+
+    fn suspend(&self, token: c_int) -> NResult<()> {
+        self.shared
+            .call_token(token, Request::Suspend, PendingKind::Other)?;
+
+        if let Some(reg) = self.shared.lock_tokens().live.get_mut(&token) {
+            reg.suspended += 1;
+        }
+        Ok(())
+    }
+
+    fn resume(&self, token: c_int) -> NResult<()> {
+        self.shared
+            .call_token(token, Request::Resume, PendingKind::Other)?;
+
+        if let Some(reg) = self.shared.lock_tokens().live.get_mut(&token) {
+            reg.suspended = reg.suspended.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    fn set_state(&self, token: c_int, state: u64) -> NResult<()> {
+        self.shared
+            .call_token(
+                token,
+                |server| Request::SetState(server, state),
+                PendingKind::Other,
+            )
+            .map(drop)
+    }
+
+    fn get_state(&self, token: c_int) -> NResult<u64> {
+        self.shared
+            .call_token(token, Request::GetState, PendingKind::Other)
+    }
+
+    fn check(&self, token: c_int) -> NResult<bool> {
+        if let Some(posted) = self.check_poller(token).and_then(|check| check.poll()) {
+            return Ok(posted);
+        }
+
+        self.shared
+            .call_token(token, Request::Check, PendingKind::Other)
+            .map(|check| check == 1)
+    }
+
+    /// Polls the generation page the server shares, when it gave the token a counter.
+    fn check_poller(&self, token: c_int) -> Option<Arc<dyn CheckPoller>> {
+        let checks = self.shared.checks.read().unwrap_or_else(|e| e.into_inner());
+        checks
+            .get(&token)
+            .map(|check| check.clone() as Arc<dyn CheckPoller>)
+    }
+
+    /// Succeeds while the server is away, the registration died with it.
+    fn cancel(&self, token: c_int) -> NResult<()> {
+        match self
+            .shared
+            .call_token(token, Request::Cancel, PendingKind::Cancel(token))
+        {
+            Ok(_) => Ok(()),
+            Err(err) if err.is_retryable() => {
+                self.shared.forget(token);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn is_valid(&self, token: c_int) -> bool {
+        self.shared.lock_tokens().live.contains_key(&token)
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_int, CString};
+use std::os::fd::{BorrowedFd, OwnedFd, RawFd};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use block::ConcreteBlock;
+
+use super::{fd_id, Callback, FdId, NotifyBackend};
+use crate::queue::{Qos, Target};
+use crate::{sys, NResult, NotifyError, RegisterFlags, RegisterOptions};
+
+/// Backend calling into the system `libnotify`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DarwinBackend;
+
+impl NotifyBackend for DarwinBackend {
+    fn post(&self, name: &str) -> NResult<()> {
+        let name = CString::new(name).map_err(|_| NotifyError::InvalidName)?;
+        ns_result!(unsafe { sys::notify_post(name.as_ptr()) })
+    }
+
+    fn post_with_state(&self, name: &str, state: u64) -> NResult<()> {
+        let token = state_token(name)?;
+        ns_result!(unsafe { sys::notify_set_state(token, state) })?;
+        self.post(name)
+    }
+
+    /// `libnotify` has no atomic update, this reads then writes the state through the token
+    /// cached for the name. A concurrent writer in another process can still be overwritten.
+    fn state_compare_exchange(
+        &self,
+        name: &str,
+        expected: u64,
+        new: u64,
+        post: bool,
+    ) -> NResult<Result<u64, u64>> {
+        let token = state_token(name)?;
+        super::compare_exchange(self, token, name, expected, new, post)
+    }
+
+    fn register(&self, name: &str, cb: Callback) -> NResult<c_int> {
+        register_dispatch(name, unsafe { sys::dispatch_get_current_queue() }, cb)
+    }
+
+    fn register_with(&self, name: &str, options: &RegisterOptions, cb: Callback) -> NResult<c_int> {
+        let queue = match &options.target {
+            Target::Current => unsafe { sys::dispatch_get_current_queue() },
+            Target::Main => sys::main_queue(),
+            Target::Global(qos) => unsafe { sys::dispatch_get_global_queue(*qos as _, 0) as _ },
+            Target::Serial(label) => serial_queue(label)?,
+            Target::Executor(_) => {
+                let queue = unsafe { sys::dispatch_get_global_queue(Qos::Default as _, 0) };
+                return register_dispatch(name, queue as _, options.wrap(cb));
+            }
+        };
+
+        register_dispatch(name, queue, cb)
+    }
+
+    fn register_check(&self, name: &str) -> NResult<c_int> {
+        let name = CString::new(name).map_err(|_| NotifyError::InvalidName)?;
+
+        let mut token = 0;
+
+        match unsafe { sys::notify_register_check(name.as_ptr(), &mut token as _) } {
+            0 => Ok(token),
+            code => Err(NotifyError::from_u32(code)),
+        }
+    }
+
+    fn register_signal(&self, name: &str, sig: c_int) -> NResult<c_int> {
+        let name = CString::new(name).map_err(|_| NotifyError::InvalidName)?;
+
+        let mut token = 0;
+
+        match unsafe { sys::notify_register_signal(name.as_ptr(), sig, &mut token as _) } {
+            0 => Ok(token),
+            code => Err(NotifyError::from_u32(code)),
+        }
+    }
+
+    fn register_fd(&self, name: &str) -> NResult<(OwnedFd, c_int)> {
+        let name = CString::new(name).map_err(|_| NotifyError::InvalidName)?;
+
+        let mut fd = -1;
+        let mut token = 0;
+
+        match unsafe {
+            sys::notify_register_file_descriptor(name.as_ptr(), &mut fd as _, 0, &mut token as _)
+        } {
+            0 => {}
+            code => return Err(NotifyError::from_u32(code)),
+        }
+
+        // libnotify closes its descriptor when the token is cancelled, hand out a duplicate.
+        let dup = unsafe { BorrowedFd::borrow_raw(fd) }
+            .try_clone_to_owned()
+            .map_err(|_| NotifyError::Failed)
+            .and_then(|dup| Ok((fd_id(&dup)?, dup)));
+
+        match dup {
+            Ok((id, dup)) => {
+                let tokens = HashSet::from([token]);
+                lock_fds().insert(id, LibnotifyFd { fd, tokens });
+                Ok((dup, token))
+            }
+            Err(err) => {
+                unsafe { sys::notify_cancel(token) };
+                Err(err)
+            }
+        }
+    }
+
+    /// With [RegisterFlags::REUSE], `fd` must be a descriptor returned by this backend, and its
+    /// token stays valid until all the registrations sharing it are cancelled.
+    fn register_fd_with(
+        &self,
+        name: &str,
+        flags: RegisterFlags,
+        fd: &mut Option<OwnedFd>,
+    ) -> NResult<c_int> {
+        if !flags.contains(RegisterFlags::REUSE) {
+            let (new, token) = self.register_fd(name)?;
+            *fd = Some(new);
+            return Ok(token);
+        }
+
+        let id = fd_id(fd.as_ref().ok_or(NotifyError::InvalidFile)?)?;
+        let name = CString::new(name).map_err(|_| NotifyError::InvalidName)?;
+
+        // Held across the registration, so a concurrent cancel can't close the descriptor.
+        let mut fds = lock_fds();
+        let shared = fds.get_mut(&id).ok_or(NotifyError::InvalidFile)?;
+        let mut fd = shared.fd;
+        let mut token = 0;
+
+        match unsafe {
+            sys::notify_register_file_descriptor(
+                name.as_ptr(),
+                &mut fd as _,
+                flags.bits(),
+                &mut token as _,
+            )
+        } {
+            0 => {
+                shared.tokens.insert(token);
+                Ok(token)
+            }
+            code => Err(NotifyError::from_u32(code)),
+        }
+    }
+
+    fn suspend(&self, token: c_int) -> NResult<()> {
+        ns_result!(unsafe { sys::notify_suspend(token) })
+    }
+
+    fn resume(&self, token: c_int) -> NResult<()> {
+        ns_result!(unsafe { sys::notify_resume(token) })
+    }
+
+    fn set_state(&self, token: c_int, state: u64) -> NResult<()> {
+        ns_result!(unsafe { sys::notify_set_state(token, state) })
+    }
+
+    fn get_state(&self, token: c_int) -> NResult<u64> {
+        let mut state = 0;
+
+        match unsafe { sys::notify_get_state(token, &mut state as _) } {
+            0 => Ok(state),
+            code => Err(NotifyError::from_u32(code)),
+        }
+    }
+
+    fn check(&self, token: c_int) -> NResult<bool> {
+        let mut check = 0;
+
+        match unsafe { sys::notify_check(token, &mut check as _) } {
+            0 => Ok(check == 1),
+            code => Err(NotifyError::from_u32(code)),
+        }
+    }
+
+    fn cancel(&self, token: c_int) -> NResult<()> {
+        // libnotify closes the descriptor with the last token writing to it. Cancelling may drop a
+        // callback owning subscriptions, so the map is updated first and left unlocked.
+        lock_fds().retain(|_, shared| !(shared.tokens.remove(&token) && shared.tokens.is_empty()));
+        ns_result!(unsafe { sys::notify_cancel(token) })
+    }
+
+    fn is_valid(&self, token: c_int) -> bool {
+        unsafe { sys::notify_is_valid_token(token) }
+    }
+}
+
+fn register_dispatch(name: &str, queue: sys::dispatch_queue_t, cb: Callback) -> NResult<c_int> {
+    let name = CString::new(name).map_err(|_| NotifyError::InvalidName)?;
+
+    let mut token = 0;
+
+    // Unwinding out of a block is undefined behaviour, callbacks that still panic abort.
+    let block = ConcreteBlock::new(move |token: c_int| {
+        if std::panic::catch_unwind(AssertUnwindSafe(|| cb(token))).is_err() {
+            std::process::abort();
+        }
+    })
+    .copy();
+
+    match unsafe {
+        sys::notify_register_dispatch(
+            name.as_ptr(),
+            &mut token as _,
+            queue,
+            &*block as *const _ as _,
+        )
+    } {
+        0 => Ok(token),
+        code => Err(NotifyError::from_u32(code)),
+    }
+}
+
+/// A descriptor of `libnotify` and the tokens writing to it.
+struct LibnotifyFd {
+    fd: RawFd,
+    tokens: HashSet<c_int>,
+}
+
+/// The descriptors of `libnotify`, by the file of the duplicate handed out for them.
+fn lock_fds() -> MutexGuard<'static, HashMap<FdId, LibnotifyFd>> {
+    static FDS: OnceLock<Mutex<HashMap<FdId, LibnotifyFd>>> = OnceLock::new();
+
+    FDS.get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// A check token for `name` used to set its state, registered on first use and never cancelled.
+///
+/// Keeping the token also keeps the state alive between posts.
+fn state_token(name: &str) -> NResult<c_int> {
+    static TOKENS: OnceLock<Mutex<HashMap<String, c_int>>> = OnceLock::new();
+
+    let mut tokens = TOKENS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    if let Some(token) = tokens.get(name) {
+        return Ok(*token);
+    }
+
+    let token = DarwinBackend.register_check(name)?;
+    tokens.insert(name.into(), token);
+    Ok(token)
+}
+
+/// The serial queue labelled `label`, created on first use and kept for the life of the process.
+fn serial_queue(label: &str) -> NResult<sys::dispatch_queue_t> {
+    struct Queue(sys::dispatch_queue_t);
+
+    // Dispatch queues are thread safe.
+    unsafe impl Send for Queue {}
+
+    static QUEUES: OnceLock<Mutex<HashMap<String, Queue>>> = OnceLock::new();
+
+    let mut queues = QUEUES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    if let Some(queue) = queues.get(label) {
+        return Ok(queue.0);
+    }
+
+    let c_label = CString::new(label).map_err(|_| NotifyError::InvalidRequest)?;
+    let queue = unsafe { sys::dispatch_queue_create(c_label.as_ptr(), std::ptr::null_mut()) };
+    if queue.is_null() {
+        return Err(NotifyError::Failed);
+    }
+
+    queues.insert(label.into(), Queue(queue));
+    Ok(queue)
+}
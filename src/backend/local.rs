@@ -0,0 +1,305 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::c_int;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+
+use super::{Callback, NotifyBackend, StateCallback};
+use crate::{NResult, NotifyError};
+
+/// Backend keeping every name inside the current process.
+///
+/// Callbacks run one at a time on a dispatcher thread owned by the backend, nothing ever leaves
+/// the process. The free functions route names starting with
+/// [NotificationName::LOCAL_PREFIX](crate::NotificationName::LOCAL_PREFIX) to a process wide
+/// instance, whatever the current backend is, like Darwin does for `self.` names. That instance
+/// hands out negative tokens, so [Token::from_raw](crate::Token::from_raw) can find it.
+///
+/// # Example
+/// ```
+/// use std::sync::mpsc;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let subscription = darwin_notify::notify_register("self.tech.subcom.darwin-notify", move |token| {
+///     tx.send(token).unwrap();
+/// })
+/// .unwrap();
+///
+/// darwin_notify::notify_post("self.tech.subcom.darwin-notify").unwrap();
+/// assert_eq!(rx.recv().unwrap(), subscription.token().as_raw());
+/// ```
+pub struct LocalBackend {
+    inner: Arc<Mutex<Inner>>,
+    events: Mutex<mpsc::Sender<(c_int, u64)>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    names: HashMap<String, Name>,
+    tokens: HashMap<c_int, Token>,
+    next_token: c_int,
+    /// Hands out negative tokens, see [LocalBackend::process_local].
+    negative: bool,
+}
+
+#[derive(Default)]
+struct Name {
+    state: u64,
+    tokens: BTreeSet<c_int>,
+}
+
+struct Token {
+    name: String,
+    handler: Handler,
+    suspended: u32,
+    pending: bool,
+    posted: bool,
+}
+
+#[derive(Clone)]
+enum Handler {
+    Token(Arc<Callback>),
+    State(Arc<StateCallback>),
+    /// Only polled.
+    Check,
+}
+
+impl LocalBackend {
+    /// Backend with its own names and dispatcher thread.
+    pub fn new() -> Self {
+        let inner = Arc::new(Mutex::new(Inner::default()));
+        let (events, rx) = mpsc::channel::<(c_int, u64)>();
+
+        let dispatch = inner.clone();
+        crate::queue::spawn("darwin-notify-local".into(), move || {
+            for (token, state) in rx {
+                let handler = lock(&dispatch)
+                    .tokens
+                    .get(&token)
+                    .map(|tok| tok.handler.clone());
+
+                match handler {
+                    Some(Handler::Token(cb)) => cb(token),
+                    Some(Handler::State(cb)) => cb(token, state),
+                    Some(Handler::Check) | None => {}
+                }
+            }
+        });
+
+        Self {
+            inner,
+            events: Mutex::new(events),
+        }
+    }
+
+    /// The instance behind process-local names. Its tokens are negative, a range no other backend
+    /// uses, so a raw token can be told apart from the ones of the current backend.
+    pub(crate) fn process_local() -> Self {
+        let backend = Self::new();
+        backend.lock().negative = true;
+        backend
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        lock(&self.inner)
+    }
+
+    fn send(&self, token: c_int, state: u64) {
+        _ = self
+            .events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send((token, state));
+    }
+
+    fn post_locked(&self, inner: &mut Inner, name: &str) {
+        let Some(entry) = inner.names.get(name) else {
+            return;
+        };
+
+        for token in &entry.tokens {
+            let tok = inner.tokens.get_mut(token).unwrap();
+
+            tok.posted = true;
+            if matches!(tok.handler, Handler::Check) {
+                continue;
+            }
+
+            if tok.suspended > 0 {
+                tok.pending = true;
+            } else {
+                self.send(*token, entry.state);
+            }
+        }
+    }
+
+    fn add(&self, name: &str, handler: Handler) -> c_int {
+        let mut inner = self.lock();
+
+        inner.next_token += 1;
+        let token = match inner.negative {
+            true => -inner.next_token,
+            false => inner.next_token,
+        };
+        inner.tokens.insert(
+            token,
+            Token {
+                name: name.into(),
+                handler,
+                suspended: 0,
+                pending: false,
+                posted: true,
+            },
+        );
+        inner
+            .names
+            .entry(name.into())
+            .or_default()
+            .tokens
+            .insert(token);
+
+        token
+    }
+}
+
+impl Default for LocalBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn lock(inner: &Mutex<Inner>) -> MutexGuard<'_, Inner> {
+    inner.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Inner {
+    fn token(&mut self, token: c_int) -> NResult<&mut Token> {
+        self.tokens.get_mut(&token).ok_or(NotifyError::InvalidToken)
+    }
+
+    fn state(&self, name: &str) -> u64 {
+        self.names.get(name).map_or(0, |entry| entry.state)
+    }
+
+    /// Names nobody is registered for have no state to keep.
+    fn set_state(&mut self, name: &str, state: u64) {
+        if let Some(entry) = self.names.get_mut(name) {
+            entry.state = state;
+        }
+    }
+}
+
+impl NotifyBackend for LocalBackend {
+    fn post(&self, name: &str) -> NResult<()> {
+        self.post_locked(&mut self.lock(), name);
+        Ok(())
+    }
+
+    fn post_with_state(&self, name: &str, state: u64) -> NResult<()> {
+        let mut inner = self.lock();
+        inner.set_state(name, state);
+        self.post_locked(&mut inner, name);
+        Ok(())
+    }
+
+    fn state_compare_exchange(
+        &self,
+        name: &str,
+        expected: u64,
+        new: u64,
+        post: bool,
+    ) -> NResult<Result<u64, u64>> {
+        let mut inner = self.lock();
+
+        let prev = inner.state(name);
+        if prev != expected {
+            return Ok(Err(prev));
+        }
+
+        inner.set_state(name, new);
+        if post {
+            self.post_locked(&mut inner, name);
+        }
+        Ok(Ok(prev))
+    }
+
+    fn state_fetch_add(&self, name: &str, delta: u64, post: bool) -> NResult<u64> {
+        let mut inner = self.lock();
+
+        let prev = inner.state(name);
+        inner.set_state(name, prev.wrapping_add(delta));
+        if post {
+            self.post_locked(&mut inner, name);
+        }
+        Ok(prev)
+    }
+
+    fn register(&self, name: &str, cb: Callback) -> NResult<c_int> {
+        Ok(self.add(name, Handler::Token(Arc::new(cb))))
+    }
+
+    /// The state is captured when the name is posted.
+    fn register_state(self: Arc<Self>, name: &str, cb: StateCallback) -> NResult<c_int> {
+        Ok(self.add(name, Handler::State(Arc::new(cb))))
+    }
+
+    fn register_check(&self, name: &str) -> NResult<c_int> {
+        Ok(self.add(name, Handler::Check))
+    }
+
+    fn suspend(&self, token: c_int) -> NResult<()> {
+        self.lock().token(token)?.suspended += 1;
+        Ok(())
+    }
+
+    fn resume(&self, token: c_int) -> NResult<()> {
+        let mut inner = self.lock();
+        let tok = inner.token(token)?;
+
+        tok.suspended = tok.suspended.saturating_sub(1);
+        if tok.suspended == 0 && std::mem::take(&mut tok.pending) {
+            let name = tok.name.clone();
+            self.send(token, inner.state(&name));
+        }
+        Ok(())
+    }
+
+    fn set_state(&self, token: c_int, state: u64) -> NResult<()> {
+        let mut inner = self.lock();
+        let name = inner.token(token)?.name.clone();
+        inner.set_state(&name, state);
+        Ok(())
+    }
+
+    fn get_state(&self, token: c_int) -> NResult<u64> {
+        let mut inner = self.lock();
+        let name = inner.token(token)?.name.clone();
+        Ok(inner.state(&name))
+    }
+
+    fn check(&self, token: c_int) -> NResult<bool> {
+        Ok(std::mem::take(&mut self.lock().token(token)?.posted))
+    }
+
+    fn cancel(&self, token: c_int) -> NResult<()> {
+        let mut inner = self.lock();
+        let tok = inner
+            .tokens
+            .remove(&token)
+            .ok_or(NotifyError::InvalidToken)?;
+
+        if let Some(entry) = inner.names.get_mut(&tok.name) {
+            entry.tokens.remove(&token);
+            if entry.tokens.is_empty() {
+                inner.names.remove(&tok.name);
+            }
+        }
+
+        // The callback may own subscriptions of its own, drop it unlocked.
+        drop(inner);
+        drop(tok);
+        Ok(())
+    }
+
+    fn is_valid(&self, token: c_int) -> bool {
+        self.lock().tokens.contains_key(&token)
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::{HashMap, VecDeque};
+use std::ffi::c_int;
+use std::sync::{Mutex, MutexGuard};
+
+use super::{Callback, NotifyBackend};
+use crate::{NResult, NotifyError};
+
+/// When a [MockBackend] runs the callbacks of posted names.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Callbacks run before [post](NotifyBackend::post) returns.
+    #[default]
+    Immediate,
+    /// Callbacks are queued until [MockBackend::pump] is called.
+    Manual,
+}
+
+/// In-memory backend for tests.
+///
+/// Records every post and state change, delivers callbacks deterministically on the thread that
+/// posts (or pumps) and can be told to fail any operation on a name with a chosen [NotifyError].
+///
+/// Deliveries happen in posting order, one at a time. A post made from inside a callback is
+/// queued and delivered by the pump already running rather than recursively.
+///
+/// # Example
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use darwin_notify::backend::{self, Delivery, MockBackend};
+/// use darwin_notify::NotifyError;
+///
+/// let mock = Arc::new(MockBackend::new().with_delivery(Delivery::Manual));
+///
+/// backend::with_backend(mock.clone(), || {
+///     let seen = Arc::new(Mutex::new(Vec::new()));
+///     let sink = seen.clone();
+///     let subscription = darwin_notify::notify_register("tech.subcom.darwin-notify", move |token| {
+///         sink.lock().unwrap().push(token);
+///     })
+///     .unwrap();
+///
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+///     assert!(seen.lock().unwrap().is_empty());
+///     assert_eq!(mock.pump(), 1);
+///     assert_eq!(*seen.lock().unwrap(), [subscription.token().as_raw()]);
+///
+///     mock.fail("tech.subcom.darwin-notify", NotifyError::ServerNotFound);
+///     assert_eq!(
+///         darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap_err(),
+///         NotifyError::ServerNotFound
+///     );
+/// });
+///
+/// assert_eq!(mock.posts(), ["tech.subcom.darwin-notify"]);
+/// ```
+#[derive(Default)]
+pub struct MockBackend {
+    delivery: Delivery,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    tokens: HashMap<c_int, Token>,
+    states: HashMap<String, u64>,
+    failures: HashMap<String, NotifyError>,
+    queue: VecDeque<c_int>,
+    posts: Vec<String>,
+    state_changes: Vec<(String, u64)>,
+    next_token: c_int,
+    pumping: bool,
+}
+
+struct Token {
+    name: String,
+    /// `None` while the callback is running.
+    cb: Option<Callback>,
+    suspended: u32,
+    pending: bool,
+    posted: bool,
+}
+
+impl MockBackend {
+    /// Mock delivering callbacks immediately.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose when callbacks run.
+    pub fn with_delivery(mut self, delivery: Delivery) -> Self {
+        self.delivery = delivery;
+        self
+    }
+
+    /// Make every operation involving `name` fail with `err`, until [MockBackend::clear_failure].
+    pub fn fail(&self, name: &str, err: NotifyError) {
+        self.lock().failures.insert(name.into(), err);
+    }
+
+    /// Stop failing operations on `name`.
+    pub fn clear_failure(&self, name: &str) {
+        self.lock().failures.remove(name);
+    }
+
+    /// Names posted so far, in order.
+    pub fn posts(&self) -> Vec<String> {
+        self.lock().posts.clone()
+    }
+
+    /// Every `(name, state)` set so far, in order.
+    pub fn state_changes(&self) -> Vec<(String, u64)> {
+        self.lock().state_changes.clone()
+    }
+
+    /// Number of deliveries waiting for [MockBackend::pump].
+    pub fn pending(&self) -> usize {
+        self.lock().queue.len()
+    }
+
+    /// Run the callbacks of every queued delivery, returns how many ran.
+    ///
+    /// Returns 0 when called while another pump is running, the running pump delivers the queue.
+    pub fn pump(&self) -> usize {
+        let mut inner = self.lock();
+        if inner.pumping {
+            return 0;
+        }
+        inner.pumping = true;
+
+        let mut delivered = 0;
+        while let Some(token) = inner.queue.pop_front() {
+            let Some(cb) = inner.tokens.get_mut(&token).and_then(|tok| tok.cb.take()) else {
+                continue;
+            };
+            drop(inner);
+
+            cb(token);
+            delivered += 1;
+
+            inner = self.lock();
+            match inner.tokens.get_mut(&token) {
+                Some(tok) => tok.cb = Some(cb),
+                // Cancelled by its own callback, which may own subscriptions, drop it unlocked.
+                None => {
+                    drop(inner);
+                    drop(cb);
+                    inner = self.lock();
+                }
+            }
+        }
+
+        inner.pumping = false;
+        delivered
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record a post of `name` and queue its deliveries.
+    fn post_locked(&self, mut inner: MutexGuard<'_, Inner>, name: &str) {
+        inner.posts.push(name.into());
+
+        let mut posted = Vec::new();
+        for (token, tok) in inner.tokens.iter_mut().filter(|(_, tok)| tok.name == name) {
+            tok.posted = true;
+            if tok.suspended > 0 {
+                tok.pending = true;
+            } else {
+                posted.push(*token);
+            }
+        }
+
+        // Tokens are handed out in increasing order, deliver in registration order.
+        posted.sort_unstable();
+        inner.queue.extend(posted);
+
+        self.deliver(inner);
+    }
+
+    fn deliver(&self, inner: MutexGuard<'_, Inner>) {
+        if self.delivery == Delivery::Immediate && !inner.queue.is_empty() {
+            drop(inner);
+            self.pump();
+        }
+    }
+}
+
+impl Inner {
+    fn check_name(&self, name: &str) -> NResult<()> {
+        match self.failures.get(name) {
+            Some(err) => Err(*err),
+            None => Ok(()),
+        }
+    }
+
+    /// Set the state of `name` without a token, like the other backends a name nobody registered
+    /// for has no state to keep.
+    fn set_name_state(&mut self, name: &str, state: u64) {
+        if self.tokens.values().any(|tok| tok.name == name) {
+            self.states.insert(name.into(), state);
+        }
+        self.state_changes.push((name.into(), state));
+    }
+
+    fn token(&mut self, token: c_int) -> NResult<&mut Token> {
+        let name = &self
+            .tokens
+            .get(&token)
+            .ok_or(NotifyError::InvalidToken)?
+            .name;
+        self.check_name(name)?;
+
+        Ok(self.tokens.get_mut(&token).unwrap())
+    }
+}
+
+impl NotifyBackend for MockBackend {
+    fn post(&self, name: &str) -> NResult<()> {
+        let inner = self.lock();
+        inner.check_name(name)?;
+        self.post_locked(inner, name);
+        Ok(())
+    }
+
+    fn post_with_state(&self, name: &str, state: u64) -> NResult<()> {
+        let mut inner = self.lock();
+        inner.check_name(name)?;
+        inner.set_name_state(name, state);
+
+        self.post_locked(inner, name);
+        Ok(())
+    }
+
+    fn state_compare_exchange(
+        &self,
+        name: &str,
+        expected: u64,
+        new: u64,
+        post: bool,
+    ) -> NResult<Result<u64, u64>> {
+        let mut inner = self.lock();
+        inner.check_name(name)?;
+
+        let prev = inner.states.get(name).copied().unwrap_or(0);
+        if prev != expected {
+            return Ok(Err(prev));
+        }
+
+        inner.set_name_state(name, new);
+        if post {
+            self.post_locked(inner, name);
+        }
+        Ok(Ok(prev))
+    }
+
+    fn state_fetch_add(&self, name: &str, delta: u64, post: bool) -> NResult<u64> {
+        let mut inner = self.lock();
+        inner.check_name(name)?;
+
+        let prev = inner.states.get(name).copied().unwrap_or(0);
+        inner.set_name_state(name, prev.wrapping_add(delta));
+        if post {
+            self.post_locked(inner, name);
+        }
+        Ok(prev)
+    }
+
+    fn register(&self, name: &str, cb: Callback) -> NResult<c_int> {
+        let mut inner = self.lock();
+        inner.check_name(name)?;
+
+        inner.next_token += 1;
+        let token = inner.next_token;
+        inner.tokens.insert(
+            token,
+            Token {
+                name: name.into(),
+                cb: Some(cb),
+                suspended: 0,
+                pending: false,
+                posted: true,
+            },
+        );
+
+        Ok(token)
+    }
+
+    fn suspend(&self, token: c_int) -> NResult<()> {
+        self.lock().token(token)?.suspended += 1;
+        Ok(())
+    }
+
+    fn resume(&self, token: c_int) -> NResult<()> {
+        let mut inner = self.lock();
+        let tok = inner.token(token)?;
+
+        tok.suspended = tok.suspended.saturating_sub(1);
+        if tok.suspended == 0 && std::mem::take(&mut tok.pending) {
+            inner.queue.push_back(token);
+            self.deliver(inner);
+        }
+
+        Ok(())
+    }
+
+    fn set_state(&self, token: c_int, state: u64) -> NResult<()> {
+        let mut inner = self.lock();
+        let name = inner.token(token)?.name.clone();
+
+        inner.states.insert(name.clone(), state);
+        inner.state_changes.push((name, state));
+        Ok(())
+    }
+
+    fn get_state(&self, token: c_int) -> NResult<u64> {
+        let mut inner = self.lock();
+        let name = inner.token(token)?.name.clone();
+
+        Ok(inner.states.get(&name).copied().unwrap_or(0))
+    }
+
+    fn check(&self, token: c_int) -> NResult<bool> {
+        Ok(std::mem::take(&mut self.lock().token(token)?.posted))
+    }
+
+    fn cancel(&self, token: c_int) -> NResult<()> {
+        let mut inner = self.lock();
+        inner.token(token)?;
+
+        let tok = inner.tokens.remove(&token).unwrap();
+        if !inner.tokens.values().any(|other| other.name == tok.name) {
+            inner.states.remove(&tok.name);
+        }
+
+        // The callback may own subscriptions of its own, drop it unlocked.
+        drop(inner);
+        drop(tok);
+        Ok(())
+    }
+
+    fn is_valid(&self, token: c_int) -> bool {
+        self.lock().tokens.contains_key(&token)
+    }
+}
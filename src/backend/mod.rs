@@ -0,0 +1,416 @@
+//! Pluggable implementations of the Notify API.
+//!
+//! The free functions at the crate root ([notify_post](crate::notify_post),
+//! [notify_register](crate::notify_register), ...) don't talk to the OS directly, they dispatch to
+//! the [NotifyBackend] returned by [current]. On macOS that is `DarwinBackend` by default, which
+//! calls into `libnotify`. [DaemonBackend] provides the same semantics on top of the
+//! [notifyd](crate::notifyd) emulation server, [LocalBackend] keeps names inside the process and
+//! [MockBackend] keeps everything in memory for tests. Any other implementation can be installed
+//! process wide with [set_backend], or for the current thread only with [with_backend].
+//!
+//! Names starting with [NotificationName::LOCAL_PREFIX](crate::NotificationName::LOCAL_PREFIX)
+//! always go to a process wide [LocalBackend], see [for_name].
+
+use std::cell::RefCell;
+#[cfg(unix)]
+use std::collections::BTreeMap;
+use std::ffi::c_int;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, OwnedFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, OnceLock, RwLock};
+#[cfg(unix)]
+use std::sync::{Mutex, Weak};
+
+#[cfg(unix)]
+use crate::NotifyError;
+#[cfg(unix)]
+use crate::RegisterFlags;
+use crate::{NResult, NotificationName, RegisterOptions};
+
+#[cfg(unix)]
+mod daemon;
+#[cfg(target_os = "macos")]
+mod darwin;
+mod local;
+mod mock;
+
+#[cfg(unix)]
+pub use daemon::DaemonBackend;
+#[cfg(target_os = "macos")]
+pub use darwin::DarwinBackend;
+pub use local::LocalBackend;
+pub use mock::{Delivery, MockBackend};
+
+/// Callback invoked with the registration token every time a notification is delivered.
+///
+/// Backends may run a callback on any thread and, when it is delivered on a concurrent queue,
+/// several times at once. The crate root wraps callbacks that must run serially before they get
+/// here.
+pub type Callback = Box<dyn Fn(c_int) + Send + Sync + 'static>;
+
+/// Callback invoked with the registration token and the state of the name on every delivery.
+pub type StateCallback = Box<dyn Fn(c_int, u64) + Send + Sync + 'static>;
+
+/// The full surface of the Notify API.
+///
+/// Every method mirrors one of the free functions at the crate root, see those for details.
+pub trait NotifyBackend: Send + Sync {
+    /// Post a notification for a name.
+    fn post(&self, name: &str) -> NResult<()>;
+
+    /// Set the state of `name` and post it, so callbacks observe `state` when they run.
+    ///
+    /// The default implementation sets the state through a temporary check token before posting,
+    /// which is not atomic against other producers of the same name.
+    fn post_with_state(&self, name: &str, state: u64) -> NResult<()> {
+        let token = self.register_check(name)?;
+        let res = self.set_state(token, state).and_then(|_| self.post(name));
+        _ = self.cancel(token);
+        res
+    }
+
+    /// Set the state of `name` to `new` if it is `expected`, posting it on success when `post`
+    /// is set. Returns the previous state, `Err` when it didn't match.
+    ///
+    /// The default implementation reads and writes the state through a temporary check token,
+    /// which is not atomic against other producers of the same name.
+    fn state_compare_exchange(
+        &self,
+        name: &str,
+        expected: u64,
+        new: u64,
+        post: bool,
+    ) -> NResult<Result<u64, u64>> {
+        let token = self.register_check(name)?;
+        let res = compare_exchange(self, token, name, expected, new, post);
+        _ = self.cancel(token);
+        res
+    }
+
+    /// Add `delta` to the state of `name`, wrapping around on overflow, posting it when `post` is
+    /// set. Returns the previous state.
+    ///
+    /// The default implementation retries
+    /// [state_compare_exchange](NotifyBackend::state_compare_exchange) until it succeeds.
+    fn state_fetch_add(&self, name: &str, delta: u64, post: bool) -> NResult<u64> {
+        let mut current = 0;
+        loop {
+            match self.state_compare_exchange(name, current, current.wrapping_add(delta), post)? {
+                Ok(prev) => return Ok(prev),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Register `cb` to be called every time `name` is posted, returns the registration token.
+    fn register(&self, name: &str, cb: Callback) -> NResult<c_int>;
+
+    /// Register `cb` to be called on the queue chosen by `options` every time `name` is posted.
+    ///
+    /// The default implementation wraps `cb` to hand every call over to the emulated queue or
+    /// executor, see [RegisterOptions].
+    fn register_with(&self, name: &str, options: &RegisterOptions, cb: Callback) -> NResult<c_int> {
+        self.register(name, options.wrap(cb))
+    }
+
+    /// Register `cb` to be called with the state of `name` every time it is posted.
+    ///
+    /// The default implementation reads the state when the notification is delivered, by which
+    /// time another producer may have changed it again.
+    fn register_state(self: Arc<Self>, name: &str, cb: StateCallback) -> NResult<c_int>
+    where
+        Self: 'static,
+    {
+        let backend = Arc::downgrade(&self);
+
+        self.register(
+            name,
+            Box::new(move |token| {
+                let Some(backend) = backend.upgrade() else {
+                    return;
+                };
+                if let Ok(state) = backend.get_state(token) {
+                    cb(token, state)
+                }
+            }),
+        )
+    }
+
+    /// Register a token for `name` that is only polled with [check](NotifyBackend::check).
+    ///
+    /// The default implementation registers a callback that does nothing.
+    fn register_check(&self, name: &str) -> NResult<c_int> {
+        self.register(name, Box::new(|_| {}))
+    }
+
+    /// Register for `name` to be delivered by sending the signal `sig` to the process.
+    ///
+    /// The default implementation raises the signal from a [register](NotifyBackend::register)
+    /// callback.
+    #[cfg(unix)]
+    fn register_signal(&self, name: &str, sig: c_int) -> NResult<c_int> {
+        crate::Signal::new(sig)?;
+        self.register(name, Box::new(move |_| crate::signal::raise(sig)))
+    }
+
+    /// Register for `name` to be delivered by writing the token to a file descriptor, returns the
+    /// read end of the descriptor and the registration token.
+    ///
+    /// Every post writes the token as a 4-byte big endian integer, like
+    /// `notify_register_file_descriptor`. The default implementation feeds a socket pair from a
+    /// [register](NotifyBackend::register) callback and drops writes when the reader falls behind.
+    #[cfg(unix)]
+    fn register_fd(&self, name: &str) -> NResult<(OwnedFd, c_int)> {
+        let (reader, writer) = UnixStream::pair().map_err(|_| NotifyError::Failed)?;
+        writer
+            .set_nonblocking(true)
+            .map_err(|_| NotifyError::Failed)?;
+
+        let id = fd_id(&reader)?;
+        let writer = Arc::new(writer);
+        let token = self.register(name, write_tokens(writer.clone()))?;
+        lock_writers().insert(id, Arc::downgrade(&writer));
+
+        Ok((reader.into(), token))
+    }
+
+    /// [register_fd](NotifyBackend::register_fd) with `flags`, like
+    /// `notify_register_file_descriptor`.
+    ///
+    /// With [RegisterFlags::REUSE] the token is written to `fd`, a descriptor returned by an
+    /// earlier registration of this backend, otherwise `fd` is replaced by a new descriptor. The
+    /// default implementation reuses the socket pairs of the default
+    /// [register_fd](NotifyBackend::register_fd).
+    #[cfg(unix)]
+    fn register_fd_with(
+        &self,
+        name: &str,
+        flags: RegisterFlags,
+        fd: &mut Option<OwnedFd>,
+    ) -> NResult<c_int> {
+        if !flags.contains(RegisterFlags::REUSE) {
+            let (new, token) = self.register_fd(name)?;
+            *fd = Some(new);
+            return Ok(token);
+        }
+
+        let reader = fd_id(fd.as_ref().ok_or(NotifyError::InvalidFile)?)?;
+        let writer = lock_writers()
+            .get(&reader)
+            .and_then(Weak::upgrade)
+            .ok_or(NotifyError::InvalidFile)?;
+
+        self.register(name, write_tokens(writer))
+    }
+
+    /// Suspend delivery of notifications for a token.
+    fn suspend(&self, token: c_int) -> NResult<()>;
+
+    /// Removes one level of suspension for a token.
+    fn resume(&self, token: c_int) -> NResult<()>;
+
+    /// Set the 64-bit state value of the name associated with a token.
+    fn set_state(&self, token: c_int, state: u64) -> NResult<()>;
+
+    /// Get the 64-bit state value of the name associated with a token.
+    fn get_state(&self, token: c_int) -> NResult<u64>;
+
+    /// Check if any notifications have been posted since the last check.
+    fn check(&self, token: c_int) -> NResult<bool>;
+
+    /// A handle checking `token` without going through the backend, kept by the
+    /// [Subscription](crate::Subscription) of the token. The default implementation has none.
+    fn check_poller(&self, _token: c_int) -> Option<Arc<dyn CheckPoller>> {
+        None
+    }
+
+    /// Cancel a token and free resources associated with it.
+    fn cancel(&self, token: c_int) -> NResult<()>;
+
+    /// Whether `token` is currently registered.
+    ///
+    /// The default implementation reads the state of the token, which only fails for unknown
+    /// tokens.
+    fn is_valid(&self, token: c_int) -> bool {
+        self.get_state(token).is_ok()
+    }
+}
+
+/// Checks a token without a call into its backend, see [NotifyBackend::check_poller].
+pub trait CheckPoller: Send + Sync {
+    /// Like [NotifyBackend::check], `None` when the backend has to be asked.
+    fn poll(&self) -> Option<bool>;
+}
+
+/// Identifies an open file by device and inode, unlike a descriptor number which is reused as soon
+/// as it is closed.
+#[cfg(unix)]
+pub(crate) type FdId = (u64, u64);
+
+/// The [FdId] of the file `fd` is open on, [NotifyError::InvalidFile] if it isn't open.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // dev_t and ino_t differ between platforms
+pub(crate) fn fd_id(fd: &impl AsRawFd) -> NResult<FdId> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+
+    match unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) } {
+        0 => {
+            let stat = unsafe { stat.assume_init() };
+            Ok((stat.st_dev as u64, stat.st_ino as u64))
+        }
+        _ => Err(NotifyError::InvalidFile),
+    }
+}
+
+/// Writing ends of the descriptors returned by the default
+/// [register_fd](NotifyBackend::register_fd), by reading end. Kept alive by the registrations.
+#[cfg(unix)]
+static FD_WRITERS: Mutex<BTreeMap<FdId, Weak<UnixStream>>> = Mutex::new(BTreeMap::new());
+
+/// [FD_WRITERS], without the writers whose registrations were cancelled or whose reading end
+/// was closed.
+#[cfg(unix)]
+fn lock_writers() -> std::sync::MutexGuard<'static, BTreeMap<FdId, Weak<UnixStream>>> {
+    let mut writers = FD_WRITERS.lock().unwrap_or_else(|e| e.into_inner());
+    writers.retain(|_, writer| writer.upgrade().is_some_and(|writer| !is_hung_up(&writer)));
+    writers
+}
+
+/// Whether the other end of `stream` was closed.
+#[cfg(unix)]
+fn is_hung_up(stream: &UnixStream) -> bool {
+    let mut poll = libc::pollfd {
+        fd: stream.as_raw_fd(),
+        events: 0,
+        revents: 0,
+    };
+
+    unsafe { libc::poll(&mut poll, 1, 0) == 1 && poll.revents & libc::POLLHUP != 0 }
+}
+
+/// Callback writing the token to `writer`, dropping it when the reader falls behind.
+#[cfg(unix)]
+fn write_tokens(writer: Arc<UnixStream>) -> Callback {
+    use std::io::Write;
+
+    Box::new(move |token| _ = (&*writer).write_all(&token.to_be_bytes()))
+}
+
+/// Best-effort compare and exchange of the state of `name` through `token`.
+pub(crate) fn compare_exchange<B: NotifyBackend + ?Sized>(
+    backend: &B,
+    token: c_int,
+    name: &str,
+    expected: u64,
+    new: u64,
+    post: bool,
+) -> NResult<Result<u64, u64>> {
+    let prev = backend.get_state(token)?;
+    if prev != expected {
+        return Ok(Err(prev));
+    }
+
+    backend.set_state(token, new)?;
+    if post {
+        backend.post(name)?;
+    }
+    Ok(Ok(prev))
+}
+
+/// Environment variable choosing the default backend outside macOS, set it to `local` to keep
+/// names inside the process instead of using [notifyd](crate::notifyd).
+pub const BACKEND_ENV: &str = "DARWIN_NOTIFY_BACKEND";
+
+static GLOBAL: RwLock<Option<Arc<dyn NotifyBackend>>> = RwLock::new(None);
+
+thread_local! {
+    static SCOPED: RefCell<Option<Arc<dyn NotifyBackend>>> = const { RefCell::new(None) };
+}
+
+/// Install `backend` as the process wide backend used by the free functions.
+pub fn set_backend(backend: Arc<dyn NotifyBackend>) {
+    *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = Some(backend);
+}
+
+/// Run `f` with `backend` overriding the process wide backend on the current thread.
+///
+/// The previous backend is restored when `f` returns, even if it panics.
+pub fn with_backend<R>(backend: Arc<dyn NotifyBackend>, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Arc<dyn NotifyBackend>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let prev = self.0.take();
+            SCOPED.with(|scoped| *scoped.borrow_mut() = prev);
+        }
+    }
+
+    let _restore = Restore(SCOPED.with(|scoped| scoped.borrow_mut().replace(backend)));
+    f()
+}
+
+/// The backend the free functions dispatch to on this thread.
+///
+/// Until one is installed with [set_backend], the process wide backend is picked on first use:
+/// `DarwinBackend` on macOS, elsewhere a [DaemonBackend] for the
+/// [socket_path](crate::notifyd::socket_path) of [notifyd](crate::notifyd), or a [LocalBackend]
+/// when [BACKEND_ENV] is `local`.
+pub fn current() -> Arc<dyn NotifyBackend> {
+    if let Some(backend) = SCOPED.with(|scoped| scoped.borrow().clone()) {
+        return backend;
+    }
+
+    if let Some(backend) = GLOBAL.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return backend.clone();
+    }
+
+    GLOBAL
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(default_backend)
+        .clone()
+}
+
+/// `libnotify` on macOS.
+#[cfg(target_os = "macos")]
+fn default_backend() -> Arc<dyn NotifyBackend> {
+    Arc::new(DarwinBackend)
+}
+
+/// The [notifyd](crate::notifyd) server, connected on first use so a server started later or
+/// restarted is picked up. Names only stay inside the process when asked for with [BACKEND_ENV].
+#[cfg(all(unix, not(target_os = "macos")))]
+fn default_backend() -> Arc<dyn NotifyBackend> {
+    if std::env::var_os(BACKEND_ENV).is_some_and(|backend| backend == "local") {
+        return Arc::new(LocalBackend::new());
+    }
+
+    Arc::new(DaemonBackend::new(crate::notifyd::socket_path()))
+}
+
+/// Names stay inside the process.
+#[cfg(not(unix))]
+fn default_backend() -> Arc<dyn NotifyBackend> {
+    Arc::new(LocalBackend::new())
+}
+
+/// The backend the free functions use for `name`: the process wide [LocalBackend] for
+/// process-local names, [current] for every other name.
+pub fn for_name(name: &str) -> Arc<dyn NotifyBackend> {
+    if NotificationName::is_local_name(name) {
+        return process_local();
+    }
+
+    current()
+}
+
+/// The process wide [LocalBackend] of process-local names, whose tokens are negative.
+pub(crate) fn process_local() -> Arc<dyn NotifyBackend> {
+    static LOCAL: OnceLock<Arc<LocalBackend>> = OnceLock::new();
+
+    LOCAL
+        .get_or_init(|| Arc::new(LocalBackend::process_local()))
+        .clone()
+}
@@ -0,0 +1,24 @@
+//! Standalone notification server, see [darwin_notify::notifyd].
+//!
+//! Usage: `darwin-notifyd [SOCKET_PATH]`, the path defaults to `$DARWIN_NOTIFYD_SOCKET` or
+//! `/var/run/darwin-notifyd.sock`.
+
+#[cfg(unix)]
+fn main() {
+    let path = std::env::args_os()
+        .nth(1)
+        .map(Into::into)
+        .unwrap_or_else(darwin_notify::notifyd::socket_path);
+
+    let res = darwin_notify::notifyd::Server::bind(&path).and_then(|server| server.run());
+    if let Err(err) = res {
+        eprintln!("darwin-notifyd: {}: {err}", path.display());
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("darwin-notifyd: only Unix domain sockets are supported");
+    std::process::exit(1);
+}
@@ -0,0 +1,247 @@
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime};
+
+use crate::{Error, NotificationName, NotifyError, Subscription};
+
+/// Conversion between a value and the 64-bit state word of a name.
+///
+/// Implemented for the integer types, `bool`, `[u8; 8]`, [SystemTime] and [Timestamp]. Use
+/// [impl_state_codec](crate::impl_state_codec) for C-like enums, and implement it by hand to pack
+/// small structs.
+pub trait StateCodec: Sized {
+    /// The state word holding `self`.
+    fn encode(&self) -> u64;
+
+    /// The value held by `state`, `None` if it doesn't hold a valid `Self`.
+    fn decode(state: u64) -> Option<Self>;
+}
+
+macro_rules! unsigned {
+    ($($ty: ty),*) => {$(
+        impl StateCodec for $ty {
+            fn encode(&self) -> u64 {
+                *self as u64
+            }
+
+            fn decode(state: u64) -> Option<Self> {
+                state.try_into().ok()
+            }
+        }
+    )*};
+}
+
+// Signed values are sign extended, so a negative value reads back from any wider signed type.
+macro_rules! signed {
+    ($($ty: ty),*) => {$(
+        impl StateCodec for $ty {
+            fn encode(&self) -> u64 {
+                *self as i64 as u64
+            }
+
+            fn decode(state: u64) -> Option<Self> {
+                (state as i64).try_into().ok()
+            }
+        }
+    )*};
+}
+
+unsigned!(u8, u16, u32, u64, usize);
+signed!(i8, i16, i32, i64, isize);
+
+impl StateCodec for bool {
+    fn encode(&self) -> u64 {
+        *self as u64
+    }
+
+    fn decode(state: u64) -> Option<Self> {
+        match state {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Big endian, the first byte is the most significant one of the state.
+impl StateCodec for [u8; 8] {
+    fn encode(&self) -> u64 {
+        u64::from_be_bytes(*self)
+    }
+
+    fn decode(state: u64) -> Option<Self> {
+        Some(state.to_be_bytes())
+    }
+}
+
+/// A [SystemTime] stored as a count of `UNIT_NS` nanosecond ticks since `EPOCH_SECS` seconds
+/// after the Unix epoch.
+///
+/// The defaults give microseconds since the Unix epoch, what [SystemTime] itself uses as a
+/// codec. Times before the epoch are stored as the epoch, sub-tick precision is truncated.
+/// A zero `UNIT_NS` fails to compile.
+///
+/// # Example
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use darwin_notify::{StateCodec, Timestamp};
+///
+/// // Seconds since 2001-01-01, like CFAbsoluteTime.
+/// type Absolute = Timestamp<1_000_000_000, 978_307_200>;
+///
+/// let time = SystemTime::UNIX_EPOCH + Duration::from_secs(978_307_260);
+/// assert_eq!(Absolute::from(time).encode(), 60);
+/// assert_eq!(Absolute::decode(60).unwrap().0, time);
+/// ```
+///
+/// ```compile_fail
+/// use std::time::SystemTime;
+/// use darwin_notify::{StateCodec, Timestamp};
+///
+/// Timestamp::<0>(SystemTime::now()).encode();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp<const UNIT_NS: u64 = 1_000, const EPOCH_SECS: u64 = 0>(pub SystemTime);
+
+impl<const UNIT_NS: u64, const EPOCH_SECS: u64> Timestamp<UNIT_NS, EPOCH_SECS> {
+    fn epoch() -> SystemTime {
+        const { assert!(UNIT_NS > 0, "a Timestamp unit can't be zero nanoseconds") };
+        SystemTime::UNIX_EPOCH + Duration::from_secs(EPOCH_SECS)
+    }
+}
+
+impl<const UNIT_NS: u64, const EPOCH_SECS: u64> From<SystemTime>
+    for Timestamp<UNIT_NS, EPOCH_SECS>
+{
+    fn from(time: SystemTime) -> Self {
+        Self(time)
+    }
+}
+
+impl<const UNIT_NS: u64, const EPOCH_SECS: u64> StateCodec for Timestamp<UNIT_NS, EPOCH_SECS> {
+    fn encode(&self) -> u64 {
+        const { assert!(UNIT_NS > 0, "a Timestamp unit can't be zero nanoseconds") };
+        let since = self.0.duration_since(Self::epoch()).unwrap_or_default();
+        (since.as_nanos() / UNIT_NS as u128)
+            .try_into()
+            .unwrap_or(u64::MAX)
+    }
+
+    fn decode(state: u64) -> Option<Self> {
+        let nanos = (state as u128).checked_mul(UNIT_NS as u128)?;
+        let since = Duration::new(
+            (nanos / 1_000_000_000).try_into().ok()?,
+            (nanos % 1_000_000_000) as u32,
+        );
+
+        Self::epoch().checked_add(since).map(Self)
+    }
+}
+
+/// Microseconds since the Unix epoch, see [Timestamp] to choose another resolution or epoch.
+impl StateCodec for SystemTime {
+    fn encode(&self) -> u64 {
+        Timestamp::<1_000, 0>(*self).encode()
+    }
+
+    fn decode(state: u64) -> Option<Self> {
+        Timestamp::<1_000, 0>::decode(state).map(|time| time.0)
+    }
+}
+
+/// Implement [StateCodec] for C-like enums, storing the discriminant of each listed variant.
+///
+/// # Example
+/// ```
+/// use darwin_notify::StateCodec;
+///
+/// #[derive(Debug, PartialEq)]
+/// enum Power {
+///     Ac = 1,
+///     Battery = 2,
+/// }
+///
+/// darwin_notify::impl_state_codec!(Power { Ac, Battery });
+///
+/// assert_eq!(Power::Battery.encode(), 2);
+/// assert_eq!(Power::decode(1), Some(Power::Ac));
+/// assert_eq!(Power::decode(3), None);
+/// ```
+#[macro_export]
+macro_rules! impl_state_codec {
+    ($ty: ident { $($variant: ident),+ $(,)? }) => {
+        impl $crate::StateCodec for $ty {
+            fn encode(&self) -> u64 {
+                match self {
+                    $(Self::$variant => Self::$variant as u64,)+
+                }
+            }
+
+            fn decode(state: u64) -> Option<Self> {
+                $(if state == Self::$variant as u64 {
+                    return Some(Self::$variant);
+                })+
+                None
+            }
+        }
+    };
+}
+
+/// A name whose state holds a `T`.
+///
+/// Keeps a check token registered for the name, so the state lives as long as the `TypedName`
+/// even when nobody else is registered.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+/// use darwin_notify::TypedName;
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let charging = TypedName::<bool>::new("tech.subcom.darwin-notify.charging").unwrap();
+///     charging.set_state(&true).unwrap();
+///     assert!(charging.get_state().unwrap());
+/// });
+/// ```
+#[derive(Debug)]
+pub struct TypedName<T> {
+    name: NotificationName,
+    subscription: Subscription,
+    _codec: PhantomData<fn() -> T>,
+}
+
+impl<T: StateCodec> TypedName<T> {
+    /// Register for `name`, failing with [NotifyError::InvalidName] if it isn't a valid
+    /// [NotificationName].
+    pub fn new(name: impl Into<String>) -> Result<Self, Error> {
+        let name = NotificationName::new(name)?;
+        let subscription = crate::register_check(&name)?;
+
+        Ok(Self {
+            name,
+            subscription,
+            _codec: PhantomData,
+        })
+    }
+
+    /// The name.
+    pub fn name(&self) -> &NotificationName {
+        &self.name
+    }
+
+    /// Read the state, failing with [NotifyError::Failed] if it doesn't hold a valid `T`.
+    pub fn get_state(&self) -> Result<T, Error> {
+        T::decode(self.subscription.state()?)
+            .ok_or_else(|| NotifyError::Failed.with_name(self.name.as_str()))
+    }
+
+    /// Write the state, without posting.
+    pub fn set_state(&self, value: &T) -> Result<(), Error> {
+        self.subscription.set_state(value.encode())
+    }
+
+    /// Write the state and post the name, see [post_with_state](crate::post_with_state).
+    pub fn post_with_state(&self, value: &T) -> Result<(), Error> {
+        crate::post_with_state(&self.name, value.encode())
+    }
+}
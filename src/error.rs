@@ -0,0 +1,265 @@
+use std::ffi::c_int;
+use std::fmt;
+use std::io;
+
+use crate::sys;
+
+// Variants and codes both come from the `NOTIFY_STATUS_*` constants of the header.
+macro_rules! notify_error {
+    ($($(#[$meta: meta])* $variant: ident = $status: ident => $desc: literal,)*) => {
+        /// Errors returned by Darwin Notify API
+        ///
+        /// The free functions and [Subscription](crate::Subscription) return it wrapped in an
+        /// [Error] telling which name or token it was raised for.
+        ///
+        /// # Example
+        /// ```
+        /// use darwin_notify::NotifyError;
+        ///
+        /// assert_eq!(NotifyError::InvalidName.code(), 1);
+        /// assert_ne!(NotifyError::Unknown(1), NotifyError::InvalidName);
+        /// assert_eq!(
+        ///     NotifyError::ServerNotFound.to_string(),
+        ///     "Darwin Notify Error: notification server not found (status 9)"
+        /// );
+        /// ```
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[non_exhaustive]
+        pub enum NotifyError {
+            $($(#[$meta])* $variant,)*
+            /// A status this crate doesn't know, with its code.
+            Unknown(u32),
+        }
+
+        impl NotifyError {
+            pub(crate) fn from_u32(code: u32) -> Self {
+                match code {
+                    $(sys::$status => Self::$variant,)*
+
+                    code => {
+                        #[cfg(feature = "tracing")]
+                        tracing::error!(
+                        "darwin-notify: This is a bug, please report on github. {code} should've never been the status."
+                    );
+
+                        // just for the lints
+                        _ = code;
+
+                        NotifyError::Unknown(code)
+                    }
+                }
+            }
+
+            /// The `NOTIFY_STATUS_*` code of the error.
+            pub fn code(&self) -> u32 {
+                match self {
+                    $(Self::$variant => sys::$status,)*
+                    Self::Unknown(code) => *code,
+                }
+            }
+
+            fn describe(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $desc,)*
+                    Self::Unknown(_) => "unknown status, this is most certainly a bug. Please report issue on github",
+                }
+            }
+        }
+    };
+}
+
+notify_error! {
+    /// The name is empty, too long or rejected by the server.
+    InvalidName = NOTIFY_STATUS_INVALID_NAME => "invalid name",
+    /// The token isn't registered, or was cancelled.
+    InvalidToken = NOTIFY_STATUS_INVALID_TOKEN => "invalid token",
+    InvalidPort = NOTIFY_STATUS_INVALID_PORT => "invalid port",
+    /// The descriptor isn't one returned by a registration.
+    InvalidFile = NOTIFY_STATUS_INVALID_FILE => "invalid file descriptor",
+    InvalidSignal = NOTIFY_STATUS_INVALID_SIGNAL => "invalid signal",
+    InvalidRequest = NOTIFY_STATUS_INVALID_REQUEST => "invalid request",
+    /// The caller may not post or register for the name.
+    NotAuthorized = NOTIFY_STATUS_NOT_AUTHORIZED => "not authorized",
+    OptDisabled = NOTIFY_STATUS_OPT_DISABLE => "option disabled",
+    /// The notification server can't be reached, see [is_retryable](NotifyError::is_retryable).
+    ServerNotFound = NOTIFY_STATUS_SERVER_NOT_FOUND => "notification server not found",
+    NullInput = NOTIFY_STATUS_NULL_INPUT => "null input",
+    Failed = NOTIFY_STATUS_FAILED => "failed",
+}
+
+impl NotifyError {
+    /// Whether the same call may succeed later, when the server is back.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::ServerNotFound)
+    }
+
+    /// The error raised for `name`.
+    pub fn with_name(self, name: impl Into<String>) -> Error {
+        Error::from(self).with_name(name)
+    }
+
+    /// The error raised for the raw `token`.
+    pub fn with_token(self, token: c_int) -> Error {
+        Error::from(self).with_token(token)
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            NotifyError::InvalidName
+            | NotifyError::InvalidToken
+            | NotifyError::InvalidPort
+            | NotifyError::InvalidFile
+            | NotifyError::InvalidSignal
+            | NotifyError::InvalidRequest
+            | NotifyError::NullInput => io::ErrorKind::InvalidInput,
+            NotifyError::NotAuthorized => io::ErrorKind::PermissionDenied,
+            NotifyError::OptDisabled => io::ErrorKind::Unsupported,
+            NotifyError::ServerNotFound => io::ErrorKind::NotConnected,
+            _ => io::ErrorKind::Other,
+        }
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Darwin Notify Error: {} (status {})",
+            self.describe(),
+            self.code()
+        )
+    }
+}
+
+/// Keeps the [NotifyError] as the inner error.
+///
+/// # Example
+/// ```
+/// use std::io;
+/// use darwin_notify::NotifyError;
+///
+/// let err = io::Error::from(NotifyError::NotAuthorized);
+/// assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+/// ```
+impl From<NotifyError> for io::Error {
+    fn from(err: NotifyError) -> Self {
+        io::Error::new(err.kind(), err)
+    }
+}
+
+/// A [NotifyError] with the name or token it was raised for.
+///
+/// Compares equal to a [NotifyError] with the same [status](Error::status), match on the status
+/// to handle specific errors.
+///
+/// # Example
+/// ```
+/// use darwin_notify::NotifyError;
+///
+/// let err = darwin_notify::notify_post("").unwrap_err();
+/// assert!(matches!(err.status(), NotifyError::InvalidName));
+/// assert_eq!(err, NotifyError::InvalidName);
+/// assert_eq!(err.code(), 1);
+/// assert_eq!(err.name(), Some(""));
+/// assert_eq!(
+///     err.to_string(),
+///     r#"Darwin Notify Error: invalid name (status 1) for """#
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Error {
+    status: NotifyError,
+    name: Option<String>,
+    token: Option<c_int>,
+}
+
+impl Error {
+    /// The error without the name and token it was raised for.
+    pub fn status(&self) -> NotifyError {
+        self.status
+    }
+
+    /// The `NOTIFY_STATUS_*` code of the error.
+    pub fn code(&self) -> u32 {
+        self.status.code()
+    }
+
+    /// The name the error was raised for.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The raw token the error was raised for.
+    pub fn token(&self) -> Option<c_int> {
+        self.token
+    }
+
+    /// Whether the same call may succeed later, when the server is back.
+    pub fn is_retryable(&self) -> bool {
+        self.status.is_retryable()
+    }
+
+    /// Attach the name the error was raised for, keeping one already attached.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name.get_or_insert_with(|| name.into());
+        self
+    }
+
+    /// Attach the raw token the error was raised for, keeping one already attached.
+    pub fn with_token(mut self, token: c_int) -> Self {
+        self.token.get_or_insert(token);
+        self
+    }
+}
+
+impl From<NotifyError> for Error {
+    fn from(status: NotifyError) -> Self {
+        Self {
+            status,
+            name: None,
+            token: None,
+        }
+    }
+}
+
+impl PartialEq<NotifyError> for Error {
+    fn eq(&self, other: &NotifyError) -> bool {
+        self.status == *other
+    }
+}
+
+impl PartialEq<Error> for NotifyError {
+    fn eq(&self, other: &Error) -> bool {
+        *self == other.status
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.status)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.status.fmt(f)?;
+
+        match (&self.name, self.token) {
+            (Some(name), Some(token)) => write!(f, " for {name:?}, token {token}"),
+            (Some(name), None) => write!(f, " for {name:?}"),
+            (None, Some(token)) => write!(f, " for token {token}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Keeps the [Error] as the inner error.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::new(err.status.kind(), err)
+    }
+}
+
+pub type NResult<T> = Result<T, NotifyError>;
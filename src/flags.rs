@@ -0,0 +1,13 @@
+use std::ffi::c_int;
+
+use crate::sys;
+
+bitflags::bitflags! {
+    /// Flags of a registration, the `NOTIFY_*` flags of `notify.h`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct RegisterFlags: c_int {
+        /// Deliver on the descriptor of an earlier registration instead of a new one, see
+        /// [register_fd_with](crate::register_fd_with).
+        const REUSE = sys::NOTIFY_REUSE as c_int;
+    }
+}
@@ -3,87 +3,28 @@
 //! Find the API docs on [official Apple docs](https://developer.apple.com/documentation/darwinnotify)
 //!
 
-use std::ffi::CString;
-
-use block::ConcreteBlock;
-
-#[cfg(not(feature = "sys"))]
+// Only the constants are used outside macOS, nothing links against the functions there.
+#[cfg(not(all(target_os = "macos", feature = "sys")))]
 mod sys;
 
-#[cfg(feature = "sys")]
+#[cfg(all(target_os = "macos", feature = "sys"))]
 /// Contains raw C bindings to Darwin Notify API.
 ///
-/// This module is only availabe when `sys` feature is enabled.
+/// This module is only availabe on macOS when `sys` feature is enabled.
 pub mod sys;
 
-#[link(name = "CoreFoundation", kind = "framework")]
-extern "C" {
-    pub fn CFRunLoopRun();
-}
-
-/// Errors returned by Darwin Notify API
-#[derive(Debug)]
-#[repr(u32)]
-pub enum NotifyError {
-    InvalidName = 1,
-    InvalidToken,
-    InvalidPort,
-    InvalidFile,
-    InvalidSignal,
-    InvalidRequest,
-    NotAuthorized,
-    OptDisabled,
-    ServerNotFound,
-    NullInput,
-
-    Failed = 1000000,
-
-    Unknown = u32::MAX,
-}
-
-impl NotifyError {
-    fn from_u32(code: u32) -> Self {
-        match code {
-            1 => Self::InvalidName,
-            2 => Self::InvalidToken,
-            3 => Self::InvalidPort,
-            4 => Self::InvalidFile,
-            5 => Self::InvalidSignal,
-            6 => Self::InvalidRequest,
-            7 => Self::NotAuthorized,
-            8 => Self::OptDisabled,
-            9 => Self::ServerNotFound,
-            10 => Self::NullInput,
-            1000000 => Self::Failed,
-
-            code => {
-                #[cfg(feature = "tracing")]
-                tracing::error!(
-                "darwin-notify: This is a bug, please report on github. {code} should've never been the status."
-            );
-
-                // just for the lints
-                _ = code;
-
-                NotifyError::Unknown
-            }
-        }
-    }
-}
-
-impl std::error::Error for NotifyError {}
-
-impl std::fmt::Display for NotifyError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Unknown => f.write_str("Darwin Notify Error: Unknown error, this is most certainly a bug. Please report issue on github."),
-            err => f.write_str(&format!("Darwin Notify Error: {err:?}"))
-        }
-    }
+/// Run the `CFRunLoop` of the current thread forever.
+///
+/// # Safety
+/// Must be called from a thread allowed to run a `CFRunLoop`.
+#[cfg(target_os = "macos")]
+#[deprecated(note = "use NotifyLoop, which can be stopped and works outside macOS")]
+#[allow(non_snake_case)]
+pub unsafe fn CFRunLoopRun() {
+    sys::CFRunLoopRun()
 }
 
-pub type NResult<T> = Result<T, NotifyError>;
-
+#[cfg(target_os = "macos")]
 macro_rules! ns_result {
     ($e: expr) => {
         match $e {
@@ -93,87 +34,452 @@ macro_rules! ns_result {
     };
 }
 
+pub mod backend;
+mod codec;
+mod error;
+mod flags;
+mod name;
+#[cfg(unix)]
+pub mod notifyd;
+mod panic;
+#[cfg(unix)]
+mod proto;
+mod queue;
+mod retry;
+mod runloop;
+#[cfg(unix)]
+mod shm;
+#[cfg(unix)]
+mod signal;
+#[cfg(feature = "tokio")]
+mod stream;
+mod subscription;
+mod token;
+mod watch;
+
+pub use codec::{StateCodec, Timestamp, TypedName};
+pub use error::{Error, NResult, NotifyError};
+pub use flags::RegisterFlags;
+pub use name::NotificationName;
+pub use panic::{
+    clear_panic_hook, panic_policy, set_panic_hook, set_panic_policy, CallbackPanic, PanicPolicy,
+};
+pub use queue::{Executor, Job, Qos, RegisterOptions};
+pub use retry::{
+    clear_resubscribe_hook, retry_policy, set_resubscribe_hook, set_retry_policy, Resubscribed,
+    RetryPolicy,
+};
+pub use runloop::{NotifyLoop, Stopper};
+#[cfg(unix)]
+pub use signal::Signal;
+#[cfg(target_os = "linux")]
+pub use signal::SignalFd;
+#[cfg(feature = "tokio")]
+pub use stream::{subscribe, subscribe_state, Notification, NotificationStream, StateStream};
+pub use subscription::Subscription;
+pub use token::Token;
+pub use watch::{watch_state, StateChange};
+
 /// Post a notification for a name
 ///
+/// Fails with [NotifyError::InvalidName] if the name isn't a valid [NotificationName], and with
+/// [NotifyError::ServerNotFound] outside macOS while `darwin-notifyd` isn't running.
+///
 /// # Example
-/// ```
+/// ```no_run
 /// darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap()
 /// ```
-pub fn notify_post(name: &str) -> NResult<()> {
-    let name = CString::new(name).unwrap();
-    ns_result!(unsafe { sys::notify_post(name.as_ptr()) })
+pub fn notify_post(name: &str) -> Result<(), Error> {
+    NotificationName::validate(name)?;
+    let backend = backend::for_name(name);
+    retry::retry(|| backend.post(name)).map_err(|err| err.with_name(name))
+}
+
+/// Set the state of a name and post it in one operation.
+///
+/// Unlike [notify_set_state] followed by [notify_post], callbacks are guaranteed to observe
+/// `state` when they run. With [backend::DaemonBackend] both happen in a single request. On Darwin
+/// the state is set through a token cached for the name, which also keeps the state alive while
+/// nobody else is registered; a concurrent producer may still overwrite it before delivery.
+///
+/// # Example
+/// ```
+/// use std::sync::{mpsc, Arc};
+/// use darwin_notify::backend::{self, MockBackend};
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let (tx, rx) = mpsc::channel();
+///     let subscription = darwin_notify::notify_register("tech.subcom.darwin-notify", move |token| {
+///         tx.send(token).unwrap();
+///     })
+///     .unwrap();
+///
+///     darwin_notify::post_with_state("tech.subcom.darwin-notify", 7).unwrap();
+///     assert_eq!(rx.recv().unwrap(), subscription.token().as_raw());
+///     assert_eq!(subscription.state().unwrap(), 7);
+/// });
+/// ```
+pub fn post_with_state(name: &str, state: u64) -> Result<(), Error> {
+    NotificationName::validate(name)?;
+    let backend = backend::for_name(name);
+    retry::retry(|| backend.post_with_state(name, state)).map_err(|err| err.with_name(name))
+}
+
+/// Set the state of a name to `new` if it currently is `expected`, posting the name on success
+/// when `post` is set.
+///
+/// Returns the previous state, as `Err` when it didn't match `expected` and nothing changed. The
+/// exchange is atomic with [backend::DaemonBackend]. `libnotify` has no such operation, on Darwin
+/// it is emulated by reading then writing the state and may lose a race with another process.
+///
+/// Like [notify_set_state], the state of a name only lives while a token is registered for it.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let owner = darwin_notify::register_check("tech.subcom.darwin-notify").unwrap();
+///
+///     let claim = |pid| darwin_notify::state_compare_exchange("tech.subcom.darwin-notify", 0, pid, true);
+///     assert_eq!(claim(42).unwrap(), Ok(0));
+///     assert_eq!(claim(43).unwrap(), Err(42));
+///     assert_eq!(owner.state().unwrap(), 42);
+/// });
+/// ```
+pub fn state_compare_exchange(
+    name: &str,
+    expected: u64,
+    new: u64,
+    post: bool,
+) -> Result<Result<u64, u64>, Error> {
+    NotificationName::validate(name)?;
+    backend::for_name(name)
+        .state_compare_exchange(name, expected, new, post)
+        .map_err(|err| err.with_name(name))
+}
+
+/// Add `delta` to the state of a name, wrapping around on overflow, and return the previous
+/// state. The name is posted afterwards when `post` is set.
+///
+/// Atomic with [backend::DaemonBackend], on Darwin it retries [state_compare_exchange] until it
+/// succeeds, with the same caveats.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let counter = darwin_notify::register_check("tech.subcom.darwin-notify").unwrap();
+///
+///     assert_eq!(darwin_notify::state_fetch_add("tech.subcom.darwin-notify", 2, false).unwrap(), 0);
+///     assert_eq!(darwin_notify::state_fetch_add("tech.subcom.darwin-notify", 3, false).unwrap(), 2);
+///     assert_eq!(counter.state().unwrap(), 5);
+/// });
+/// ```
+pub fn state_fetch_add(name: &str, delta: u64, post: bool) -> Result<u64, Error> {
+    NotificationName::validate(name)?;
+    backend::for_name(name)
+        .state_fetch_add(name, delta, post)
+        .map_err(|err| err.with_name(name))
 }
 
 /// Subscribe to receive notification for a name.
 ///
-/// This function uses `notify_register_dispatch` with current dispatch queue to recieve notifications.
+/// With the default `DarwinBackend` on macOS this function uses `notify_register_dispatch` with
+/// current dispatch queue to recieve notifications, so the callback may run on any thread. Calls
+/// never overlap, see [notify_register_mut].
+///
+/// The registration is cancelled when the returned [Subscription] is dropped. Panics in the
+/// callback are caught and handled according to the [PanicPolicy]. Fails with
+/// [NotifyError::InvalidName] if the name isn't a valid [NotificationName].
 ///
-/// If you want more control, enable the `sys` feature and use [sys::notify_register_dispatch].
+/// If you want more control, enable the `sys` feature and use `sys::notify_register_dispatch`.
 ///
 /// # Example
+/// ```no_run
+/// let subscription = darwin_notify::notify_register("tech.subcom.darwin-notify", |token| { println!("Got a notification: {token}") }).unwrap();
 /// ```
-/// darwin_notify::notify_register("tech.subcom.darwin-notify", |token| { println!("Got a notification: {token}") }).unwrap();
+pub fn notify_register<F>(name: &str, cb: F) -> Result<Subscription, Error>
+where
+    F: Fn(std::ffi::c_int) + Send + 'static,
+{
+    notify_register_mut(name, cb)
+}
+
+/// Subscribe to receive notification for a name with a callback that keeps its own state.
+///
+/// The callback may run on any thread but never runs concurrently with itself, a delivery that
+/// arrives while it is running waits for it to return.
+///
+/// # Example
 /// ```
-pub fn notify_register<F>(name: &str, cb: F) -> NResult<i32>
+/// use std::sync::{mpsc, Arc};
+/// use darwin_notify::backend::{self, MockBackend};
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let (tx, rx) = mpsc::channel();
+///     let mut count = 0;
+///     let _subscription = darwin_notify::notify_register_mut("tech.subcom.darwin-notify", move |_| {
+///         count += 1;
+///         tx.send(count).unwrap();
+///     })
+///     .unwrap();
+///
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+///     assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1, 2]);
+/// });
+/// ```
+pub fn notify_register_mut<F>(name: &str, cb: F) -> Result<Subscription, Error>
 where
-    F: Fn(std::ffi::c_int) + 'static,
+    F: FnMut(std::ffi::c_int) + Send + 'static,
 {
-    let name = CString::new(name).unwrap();
+    notify_register_with(name, &RegisterOptions::new(), cb)
+}
 
-    let mut token = 0;
-    let dque = unsafe { sys::dispatch_get_current_queue() };
+/// Subscribe to receive notification for a name, with the callback running where `options` says.
+///
+/// See [RegisterOptions] for the available queues and how they map onto each platform. Calls
+/// never overlap, whatever the queue. Panics in the callback are caught and handled according to
+/// the [PanicPolicy].
+pub fn notify_register_with<F>(
+    name: &str,
+    options: &RegisterOptions,
+    cb: F,
+) -> Result<Subscription, Error>
+where
+    F: FnMut(std::ffi::c_int) + Send + 'static,
+{
+    register(name, options, queue::serialize(cb))
+}
+
+/// Like [notify_register_with], but the callback may run several times at once when `options`
+/// picks a concurrent queue or executor.
+///
+/// # Example
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+/// use darwin_notify::{Qos, RegisterOptions};
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let hits = Arc::new(AtomicUsize::new(0));
+///     let counter = hits.clone();
+///     let options = RegisterOptions::new().global_queue(Qos::Utility);
+///     let _subscription =
+///         darwin_notify::notify_register_concurrent("tech.subcom.darwin-notify", &options, move |_| {
+///             counter.fetch_add(1, Ordering::Relaxed);
+///         })
+///         .unwrap();
+///
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+///     while hits.load(Ordering::Relaxed) == 0 {
+///         std::thread::yield_now();
+///     }
+/// });
+/// ```
+pub fn notify_register_concurrent<F>(
+    name: &str,
+    options: &RegisterOptions,
+    cb: F,
+) -> Result<Subscription, Error>
+where
+    F: Fn(std::ffi::c_int) + Send + Sync + 'static,
+{
+    register(name, options, Box::new(cb))
+}
+
+fn register(
+    name: &str,
+    options: &RegisterOptions,
+    cb: backend::Callback,
+) -> Result<Subscription, Error> {
+    NotificationName::validate(name)?;
+
+    let backend = backend::for_name(name);
+    let slot = std::sync::Arc::new(panic::TokenSlot::default());
+    let cb = panic::guard(name, options.panic_policy, slot.clone(), cb);
+    let token = backend
+        .register_with(name, options, cb)
+        .map_err(|err| err.with_name(name))?;
+
+    let subscription = Subscription::new(token, backend);
+    slot.set(subscription.token());
+    Ok(subscription)
+}
+
+/// Register a token for a name that is only polled with [Subscription::check].
+///
+/// No callback runs on posts, the token just records that the name was posted since the last
+/// check. The first check always returns `true`. With [backend::DaemonBackend] checks read a
+/// counter shared with the server instead of making a request.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let subscription = darwin_notify::register_check("tech.subcom.darwin-notify").unwrap();
+///     assert!(subscription.check().unwrap());
+///     assert!(!subscription.check().unwrap());
+///
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+///     assert!(subscription.check().unwrap());
+/// });
+/// ```
+pub fn register_check(name: &str) -> Result<Subscription, Error> {
+    NotificationName::validate(name)?;
+
+    let backend = backend::for_name(name);
+    let token = backend
+        .register_check(name)
+        .map_err(|err| err.with_name(name))?;
+    Ok(Subscription::new(token, backend))
+}
+
+/// Subscribe to receive notifications for a name through a file descriptor.
+///
+/// Every post makes the returned descriptor readable with the token, a 4-byte big endian integer
+/// that [read_token] decodes. This fits `poll`/`epoll`/`mio` event loops. The registration is
+/// cancelled when the [Subscription] is dropped, after which the descriptor may reach end of file.
+///
+/// # Example
+/// ```
+/// use std::fs::File;
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let (fd, subscription) = darwin_notify::register_fd("tech.subcom.darwin-notify").unwrap();
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+///
+///     let token = darwin_notify::read_token(&mut File::from(fd)).unwrap();
+///     assert_eq!(token, subscription.token().as_raw());
+/// });
+/// ```
+#[cfg(unix)]
+pub fn register_fd(name: &str) -> Result<(std::os::fd::OwnedFd, Subscription), Error> {
+    NotificationName::validate(name)?;
 
-    let block = ConcreteBlock::new(cb).copy();
+    let backend = backend::for_name(name);
+    let (fd, token) = backend
+        .register_fd(name)
+        .map_err(|err| err.with_name(name))?;
+    Ok((fd, Subscription::new(token, backend)))
+}
 
-    match unsafe {
-        sys::notify_register_dispatch(
-            name.as_ptr(),
-            &mut token as _,
-            dque,
-            &*block as *const _ as _,
-        )
-    } {
-        0 => Ok(token),
-        code => Err(NotifyError::from_u32(code)),
-    }
+/// [register_fd] with `flags`, storing the descriptor in `fd`.
+///
+/// With [RegisterFlags::REUSE] `fd` must hold a descriptor returned by an earlier registration,
+/// and the tokens of `name` are written to it as well, otherwise it fails with
+/// [NotifyError::InvalidFile]. Without it `fd` is replaced by a new descriptor.
+///
+/// # Example
+/// ```
+/// use std::fs::File;
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+/// use darwin_notify::{NotifyError, RegisterFlags};
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let mut fd = None;
+///     let _first = darwin_notify::register_fd_with("tech.subcom.first", RegisterFlags::empty(), &mut fd)
+///         .unwrap();
+///     let second = darwin_notify::register_fd_with("tech.subcom.second", RegisterFlags::REUSE, &mut fd)
+///         .unwrap();
+///
+///     darwin_notify::notify_post("tech.subcom.second").unwrap();
+///
+///     let token = darwin_notify::read_token(&mut File::from(fd.unwrap())).unwrap();
+///     assert_eq!(token, second.token().as_raw());
+///
+///     // The descriptor is closed, another file opened under its number isn't mistaken for it.
+///     let mut fd = Some(File::open("/dev/null").unwrap().into());
+///     let third = darwin_notify::register_fd_with("tech.subcom.third", RegisterFlags::REUSE, &mut fd);
+///     assert_eq!(third.unwrap_err(), NotifyError::InvalidFile);
+/// });
+/// ```
+#[cfg(unix)]
+pub fn register_fd_with(
+    name: &str,
+    flags: RegisterFlags,
+    fd: &mut Option<std::os::fd::OwnedFd>,
+) -> Result<Subscription, Error> {
+    NotificationName::validate(name)?;
+
+    let backend = backend::for_name(name);
+    let token = backend
+        .register_fd_with(name, flags, fd)
+        .map_err(|err| err.with_name(name))?;
+    Ok(Subscription::new(token, backend))
+}
+
+/// Subscribe to receive a signal every time a name is posted.
+///
+/// The signal is sent to the whole process, like `notify_register_signal` does. Install a handler
+/// for it first, or consume it with a `SignalFd` on Linux, since the default action of most
+/// signals terminates the process.
+#[cfg(unix)]
+pub fn register_signal(name: &str, sig: Signal) -> Result<Subscription, Error> {
+    NotificationName::validate(name)?;
+
+    let backend = backend::for_name(name);
+    let token = backend
+        .register_signal(name, sig.as_raw())
+        .map_err(|err| err.with_name(name))?;
+    Ok(Subscription::new(token, backend))
+}
+
+/// Read one token written to a descriptor returned by [register_fd].
+pub fn read_token(r: &mut impl std::io::Read) -> std::io::Result<std::ffi::c_int> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(std::ffi::c_int::from_be_bytes(buf))
 }
 
 /// Suspend delivery of notifcations
-pub fn notify_suspend(token: std::ffi::c_int) -> NResult<()> {
-    ns_result!(unsafe { sys::notify_suspend(token) })
+pub fn notify_suspend(token: Token) -> Result<(), Error> {
+    let (raw, backend) = token.resolve()?;
+    backend.suspend(raw).map_err(|err| err.with_token(raw))
 }
 
 /// Set or get a state value associated with a notification token.
-pub fn notify_set_state(token: std::ffi::c_int, state: u64) -> NResult<()> {
-    ns_result!(unsafe { sys::notify_set_state(token, state) })
+pub fn notify_set_state(token: Token, state: u64) -> Result<(), Error> {
+    let (raw, backend) = token.resolve()?;
+    backend
+        .set_state(raw, state)
+        .map_err(|err| err.with_token(raw))
 }
 
 /// Get the 64-bit integer state value.
-pub fn notify_get_state(token: std::ffi::c_int) -> NResult<u64> {
-    let mut state = 0;
-
-    match unsafe { sys::notify_get_state(token, &mut state as _) } {
-        0 => Ok(state),
-        code => Err(NotifyError::from_u32(code)),
-    }
+pub fn notify_get_state(token: Token) -> Result<u64, Error> {
+    let (raw, backend) = token.resolve()?;
+    backend.get_state(raw).map_err(|err| err.with_token(raw))
 }
 
 /// Check if any notifications have been posted.
-pub fn notify_check(token: std::ffi::c_int) -> NResult<bool> {
-    let mut check = 0;
-
-    match unsafe { sys::notify_check(token, &mut check as _) } {
-        0 => Ok(check == 1),
-        code => Err(NotifyError::from_u32(code)),
-    }
+pub fn notify_check(token: Token) -> Result<bool, Error> {
+    let (raw, backend) = token.resolve()?;
+    backend.check(raw).map_err(|err| err.with_token(raw))
 }
 
 /// Cancel notification and free resources associated with a notification token.
-pub fn notify_cancel(token: std::ffi::c_int) -> NResult<()> {
-    ns_result!(unsafe { sys::notify_cancel(token) })
+///
+/// Later uses of `token` fail with [NotifyError::InvalidToken].
+pub fn notify_cancel(token: Token) -> Result<(), Error> {
+    let (raw, backend) = token.resolve()?;
+    backend.cancel(raw).map_err(|err| err.with_token(raw))?;
+    token.forget();
+    Ok(())
 }
 
 /// Removes one level of suspension for a token previously suspended by a call to notify_suspend
-pub fn notify_resume(token: std::ffi::c_int) -> NResult<()> {
-    ns_result!(unsafe { sys::notify_resume(token) })
+pub fn notify_resume(token: Token) -> Result<(), Error> {
+    let (raw, backend) = token.resolve()?;
+    backend.resume(raw).map_err(|err| err.with_token(raw))
 }
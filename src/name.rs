@@ -0,0 +1,147 @@
+use std::ffi::CString;
+
+use crate::{Error, NotifyError};
+
+/// A validated notification name.
+///
+/// Names must be non-empty, at most [NotificationName::MAX_LEN] bytes long and must not contain
+/// NUL bytes. Names under one of [NotificationName::RESERVED_PREFIXES] are accepted but flagged by
+/// [NotificationName::is_reserved], only the system is supposed to post them.
+///
+/// Dereferences to `str`, so it can be passed anywhere a name is expected.
+///
+/// # Example
+/// ```
+/// use darwin_notify::{NotificationName, NotifyError};
+///
+/// let name = NotificationName::new("tech.subcom.darwin-notify").unwrap();
+/// assert!(!name.is_reserved());
+/// assert_eq!(NotificationName::new("user.uid.501.tech.subcom").unwrap().user_uid(), Some(501));
+/// assert!(NotificationName::new("com.apple.system.config.network_change").unwrap().is_reserved());
+///
+/// assert_eq!(NotificationName::new("bad\0name").unwrap_err(), NotifyError::InvalidName);
+/// assert_eq!(darwin_notify::notify_post("bad\0name").unwrap_err(), NotifyError::InvalidName);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NotificationName(String);
+
+impl NotificationName {
+    /// Longest accepted name, in bytes.
+    pub const MAX_LEN: usize = 512;
+
+    /// Prefixes of names reserved for the system.
+    pub const RESERVED_PREFIXES: &'static [&'static str] = &["com.apple."];
+
+    /// Prefix of names scoped to a user, followed by the uid and a dot, as in `user.uid.501.`.
+    pub const USER_PREFIX: &'static str = "user.uid.";
+
+    /// Prefix of names only delivered inside the posting process.
+    pub const LOCAL_PREFIX: &'static str = "self.";
+
+    /// Validate `name`, failing with [NotifyError::InvalidName].
+    pub fn new(name: impl Into<String>) -> Result<Self, Error> {
+        let name = name.into();
+        Self::validate(&name)?;
+        Ok(Self(name))
+    }
+
+    /// Check `name` against the same rules as [NotificationName::new] without taking ownership.
+    pub fn validate(name: &str) -> Result<(), Error> {
+        if name.is_empty() || name.len() > Self::MAX_LEN || name.contains('\0') {
+            return Err(NotifyError::InvalidName.with_name(name));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the name falls under one of [NotificationName::RESERVED_PREFIXES].
+    pub fn is_reserved(&self) -> bool {
+        Self::RESERVED_PREFIXES
+            .iter()
+            .any(|prefix| self.0.starts_with(prefix))
+    }
+
+    /// The uid the name is scoped to, if it starts with [NotificationName::USER_PREFIX].
+    ///
+    /// Only that user and root may use such names.
+    pub fn user_uid(&self) -> Option<u32> {
+        Self::user_uid_of(&self.0)
+    }
+
+    pub(crate) fn user_uid_of(name: &str) -> Option<u32> {
+        let (uid, _) = name.strip_prefix(Self::USER_PREFIX)?.split_once('.')?;
+        uid.parse().ok()
+    }
+
+    /// Whether the name starts with [NotificationName::LOCAL_PREFIX].
+    ///
+    /// The free functions hand such names to an in-process
+    /// [LocalBackend](crate::backend::LocalBackend) on every platform, they never reach
+    /// `libnotify` or the [notifyd](crate::notifyd) server.
+    pub fn is_local(&self) -> bool {
+        Self::is_local_name(&self.0)
+    }
+
+    pub(crate) fn is_local_name(name: &str) -> bool {
+        name.starts_with(Self::LOCAL_PREFIX)
+    }
+
+    /// The name as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The name as a C string, never fails since NUL bytes are rejected at construction.
+    pub fn to_c_string(&self) -> CString {
+        CString::new(self.0.as_str()).unwrap()
+    }
+
+    /// Unwrap the inner string.
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl std::ops::Deref for NotificationName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for NotificationName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for NotificationName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for NotificationName {
+    type Error = Error;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Self::new(name)
+    }
+}
+
+impl TryFrom<&str> for NotificationName {
+    type Error = Error;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        Self::new(name)
+    }
+}
+
+impl std::str::FromStr for NotificationName {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::new(name)
+    }
+}
@@ -0,0 +1,653 @@
+//! A `notifyd` compatible server for platforms without Darwin's notification center.
+//!
+//! The server keeps the same model as Darwin: clients post names, register tokens for names,
+//! and every name carries a 64-bit state value for as long as at least one token is registered
+//! for it. Clients talk to it over a Unix domain socket through
+//! [DaemonBackend](crate::backend::DaemonBackend), the `darwin-notifyd` binary runs it standalone.
+//!
+//! Like on Darwin, the server learns the uid of every client from the socket and fails with
+//! [NotAuthorized](NotifyError::NotAuthorized) when
+//! - a client other than root posts or sets the state of a name under
+//!   [RESERVED_PREFIXES](crate::NotificationName::RESERVED_PREFIXES),
+//! - a client other than root or that user uses a name under
+//!   [USER_PREFIX](crate::NotificationName::USER_PREFIX) scoped to another uid.
+//!
+//! Names are checked with [NotificationName::validate] whatever the client did, and a client that
+//! stops reading is disconnected once a few thousand messages are queued for it.
+//!
+//! # Example
+//! ```
+//! use std::sync::{mpsc, Arc};
+//! use darwin_notify::{backend::DaemonBackend, notifyd::Server, NotifyError};
+//!
+//! let path = std::env::temp_dir().join(format!("darwin-notifyd-doc-{}.sock", std::process::id()));
+//! let server = Server::bind(&path).unwrap();
+//! std::thread::spawn(move || server.run());
+//!
+//! let backend = DaemonBackend::connect(&path).unwrap();
+//! darwin_notify::backend::with_backend(Arc::new(backend), || {
+//!     let (tx, rx) = mpsc::channel();
+//!     let subscription = darwin_notify::notify_register("tech.subcom.darwin-notify", move |token| {
+//!         tx.send(token).unwrap();
+//!     })
+//!     .unwrap();
+//!
+//!     subscription.set_state(42).unwrap();
+//!     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+//!
+//!     assert_eq!(rx.recv().unwrap(), subscription.token().as_raw());
+//!     assert_eq!(subscription.state().unwrap(), 42);
+//!
+//!     darwin_notify::post_with_state("tech.subcom.darwin-notify", 43).unwrap();
+//!     assert_eq!(rx.recv().unwrap(), subscription.token().as_raw());
+//!     assert_eq!(subscription.state().unwrap(), 43);
+//!
+//!     assert_eq!(darwin_notify::state_fetch_add("tech.subcom.darwin-notify", 2, false), Ok(43));
+//!     assert_eq!(
+//!         darwin_notify::state_compare_exchange("tech.subcom.darwin-notify", 45, 0, true),
+//!         Ok(Ok(45))
+//!     );
+//!     assert_eq!(rx.recv().unwrap(), subscription.token().as_raw());
+//!     assert_eq!(subscription.state().unwrap(), 0);
+//!
+//!     let (changes, rx) = mpsc::channel();
+//!     let _watch = darwin_notify::watch_state("tech.subcom.darwin-notify", move |change| {
+//!         changes.send(change).unwrap();
+//!     })
+//!     .unwrap();
+//!     darwin_notify::post_with_state("tech.subcom.darwin-notify", 5).unwrap();
+//!     darwin_notify::post_with_state("tech.subcom.darwin-notify", 6).unwrap();
+//!     assert_eq!(rx.recv().unwrap(), darwin_notify::StateChange { old: 0, new: 5 });
+//!     assert_eq!(rx.recv().unwrap(), darwin_notify::StateChange { old: 5, new: 6 });
+//!
+//!     let uid = unsafe { libc::geteuid() };
+//!     assert_eq!(darwin_notify::notify_post(&format!("user.uid.{uid}.tech.subcom")), Ok(()));
+//!     let privileged = if uid == 0 { Ok(()) } else { Err(NotifyError::NotAuthorized) };
+//!     let post = darwin_notify::notify_post("com.apple.tech.subcom").map_err(|err| err.status());
+//!     assert_eq!(post, privileged);
+//!
+//!     let check = darwin_notify::register_check("tech.subcom.darwin-notify").unwrap();
+//!     assert!(check.check().unwrap());
+//!     assert!(!check.check().unwrap());
+//!     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+//!     assert!(check.check().unwrap());
+//! });
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_int;
+use std::fs::Permissions;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+
+use crate::proto::{self, Message, Reply, Request};
+use crate::shm::{self, Shm};
+use crate::{NotificationName, NotifyError};
+
+#[cfg(test)]
+mod tests;
+
+/// Socket the server listens on and clients connect to when [SOCKET_ENV] is not set.
+pub const DEFAULT_SOCKET_PATH: &str = "/var/run/darwin-notifyd.sock";
+
+/// Environment variable overriding [DEFAULT_SOCKET_PATH].
+pub const SOCKET_ENV: &str = "DARWIN_NOTIFYD_SOCKET";
+
+/// Mode of the server socket, read and write for everyone.
+const SOCKET_MODE: u32 = 0o666;
+
+/// Messages queued for a client before it is disconnected for not reading them.
+const QUEUE_LEN: usize = 4096;
+
+/// Path of the server socket, taken from [SOCKET_ENV] or [DEFAULT_SOCKET_PATH].
+pub fn socket_path() -> PathBuf {
+    std::env::var_os(SOCKET_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH))
+}
+
+/// Path of the generation page shared by the server listening on `socket`.
+pub(crate) fn shm_path(socket: &Path) -> PathBuf {
+    let mut path = socket.as_os_str().to_owned();
+    path.push(".shm");
+    path.into()
+}
+
+/// Notification server listening on a Unix domain socket.
+///
+/// Next to the socket the server keeps a `.shm` file with a generation counter per name that
+/// has check tokens, clients map it to poll those tokens without a request.
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    registry: Arc<Mutex<Registry>>,
+    stopped: Arc<AtomicBool>,
+}
+
+/// Stops a running [Server] from any thread, see [Server::stopper].
+///
+/// Stopping a server that isn't running makes its next run return immediately.
+#[derive(Clone)]
+pub struct ServerStopper {
+    path: PathBuf,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Server {
+    /// Listen on `path`, replacing a stale socket left over by a previous server.
+    ///
+    /// Like on Darwin every user may connect, the socket is made accessible to all regardless of
+    /// the umask and [authorization](self) happens per request. Restrict it afterwards with
+    /// [std::fs::set_permissions] to keep other users out.
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        if UnixStream::connect(path).is_err() {
+            _ = std::fs::remove_file(path);
+        }
+
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, Permissions::from_mode(SOCKET_MODE))?;
+
+        let shm = match Shm::create(&shm_path(path)) {
+            Ok(shm) => Some(shm),
+            Err(err) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    "darwin-notifyd: checks fall back to requests, no shared page: {err}"
+                );
+
+                // just for the lints
+                _ = err;
+                None
+            }
+        };
+
+        Ok(Self {
+            listener,
+            path: path.to_owned(),
+            registry: Arc::new(Mutex::new(Registry::new(shm))),
+            stopped: Default::default(),
+        })
+    }
+
+    /// A handle stopping this server.
+    pub fn stopper(&self) -> ServerStopper {
+        ServerStopper {
+            path: self.path.clone(),
+            stopped: self.stopped.clone(),
+        }
+    }
+
+    /// Accept and serve clients, one thread per connection.
+    ///
+    /// Returns on accept errors, or once stopped with a [ServerStopper]. Stopping disconnects
+    /// every client and removes the socket, like the server exiting.
+    pub fn run(self) -> io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            if self.stopped.load(Ordering::Acquire) {
+                self.close();
+                return Ok(());
+            }
+
+            let registry = self.registry.clone();
+
+            std::thread::spawn(move || {
+                if let Err(err) = serve(stream, registry) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("darwin-notifyd: client disconnected: {err}");
+
+                    // just for the lints
+                    _ = err;
+                }
+            });
+        }
+    }
+}
+
+impl Server {
+    fn close(&self) {
+        _ = std::fs::remove_file(&self.path);
+        _ = std::fs::remove_file(shm_path(&self.path));
+
+        // The clients' threads see the end of their stream and drop their registrations.
+        for client in lock(&self.registry).clients.values() {
+            _ = client.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl ServerStopper {
+    /// Make the server return from its current (or next) run.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+
+        // Wakes up the accept.
+        _ = UnixStream::connect(&self.path);
+    }
+}
+
+impl std::fmt::Debug for ServerStopper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerStopper")
+            .field("path", &self.path)
+            .field("stopped", &self.stopped.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+struct Registry {
+    names: HashMap<String, Name>,
+    clients: HashMap<u64, Client>,
+    next_client: u64,
+    shm: Option<Shm>,
+    free_slots: Vec<u32>,
+}
+
+#[derive(Default)]
+struct Name {
+    state: u64,
+    tokens: HashSet<(u64, c_int)>,
+    /// Generation counter in the shared page, while check tokens are registered.
+    slot: Option<u32>,
+    checks: usize,
+}
+
+struct Client {
+    uid: u32,
+    /// Shut down to disconnect the client.
+    stream: UnixStream,
+    tx: mpsc::SyncSender<Message>,
+    tokens: HashMap<c_int, Token>,
+    next_token: c_int,
+}
+
+struct Token {
+    name: String,
+    kind: Kind,
+    suspended: u32,
+    pending: bool,
+    posted: bool,
+}
+
+/// How a token is told about posts.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Deliver,
+    /// Deliveries carry the state of the name.
+    DeliverState,
+    /// Only polled.
+    Check,
+}
+
+impl Kind {
+    fn delivery(self, token: c_int, state: u64) -> Option<Message> {
+        match self {
+            Self::Deliver => Some(Message::Deliver(token)),
+            Self::DeliverState => Some(Message::DeliverState(token, state)),
+            Self::Check => None,
+        }
+    }
+}
+
+impl Client {
+    /// Queue `msg`, disconnecting a client that stopped reading and filled its queue.
+    fn send(&self, msg: Message) {
+        if let Err(mpsc::TrySendError::Full(_)) = self.tx.try_send(msg) {
+            _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn lock(registry: &Mutex<Registry>) -> MutexGuard<'_, Registry> {
+    registry.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Whether a client running as `uid` may use `name`, `write` for posts and state changes.
+fn authorize(uid: u32, name: &str, write: bool) -> Result<(), NotifyError> {
+    if uid == 0 {
+        return Ok(());
+    }
+
+    let reserved = NotificationName::RESERVED_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix));
+    if write && reserved {
+        return Err(NotifyError::NotAuthorized);
+    }
+
+    match NotificationName::user_uid_of(name) {
+        Some(owner) if owner != uid => Err(NotifyError::NotAuthorized),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    use std::os::fd::AsRawFd;
+
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    match unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut _ as _,
+            &mut len,
+        )
+    } {
+        0 => Ok(cred.uid),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    use std::os::fd::AsRawFd;
+
+    let (mut uid, mut gid) = (0, 0);
+
+    match unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } {
+        0 => Ok(uid),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn serve(stream: UnixStream, registry: Arc<Mutex<Registry>>) -> io::Result<()> {
+    let uid = peer_uid(&stream)?;
+    let (tx, rx) = mpsc::sync_channel::<Message>(QUEUE_LEN);
+    let mut writer = stream.try_clone()?;
+    let closer = stream.try_clone()?;
+
+    // Writes go through their own thread so a client that stops reading only stalls itself, until
+    // its queue fills up and it is disconnected.
+    std::thread::spawn(move || {
+        for msg in rx {
+            if proto::write_frame(&mut writer, &msg.encode()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let id = {
+        let mut registry = lock(&registry);
+        let id = registry.next_client;
+        registry.next_client += 1;
+        registry.clients.insert(
+            id,
+            Client {
+                uid,
+                stream: closer,
+                tx,
+                tokens: HashMap::new(),
+                next_token: 1,
+            },
+        );
+        id
+    };
+
+    let mut reader = stream;
+    let res = (|| loop {
+        let frame = proto::read_frame(&mut reader)?;
+        let (seq, req) = Request::decode(&frame)?;
+
+        let mut registry = lock(&registry);
+        let (status, value) = match registry.handle(id, req) {
+            Ok(value) => (0, value),
+            Err(err) => (err.code(), 0),
+        };
+
+        if let Some(client) = registry.clients.get(&id) {
+            client.send(Message::Reply(Reply { seq, status, value }));
+        }
+    })();
+
+    lock(&registry).disconnect(id);
+    res
+}
+
+impl Registry {
+    fn new(shm: Option<Shm>) -> Self {
+        let free_slots = match shm {
+            Some(_) => (0..shm::SLOTS).rev().collect(),
+            None => Vec::new(),
+        };
+
+        Self {
+            names: HashMap::new(),
+            clients: HashMap::new(),
+            next_client: 0,
+            shm,
+            free_slots,
+        }
+    }
+
+    fn handle(&mut self, id: u64, req: Request) -> Result<u64, NotifyError> {
+        let uid = self.clients.get(&id).ok_or(NotifyError::Failed)?.uid;
+
+        let named = match &req {
+            Request::Post(name)
+            | Request::PostState(name, _)
+            | Request::StateCompareExchange(name, ..)
+            | Request::StateFetchAdd(name, ..) => Some((name, true)),
+            Request::Register(name)
+            | Request::RegisterState(name)
+            | Request::RegisterCheck(name) => Some((name, false)),
+            Request::SetState(token, _) => {
+                authorize(uid, &self.token(id, *token)?.name, true)?;
+                None
+            }
+            _ => None,
+        };
+
+        // Clients are not trusted to have validated the name.
+        if let Some((name, write)) = named {
+            NotificationName::validate(name).map_err(|err| err.status())?;
+            authorize(uid, name, write)?;
+        }
+
+        match req {
+            Request::Post(name) => {
+                self.post(&name);
+                Ok(0)
+            }
+            Request::PostState(name, state) => {
+                self.set_name_state(&name, state);
+                self.post(&name);
+                Ok(0)
+            }
+            Request::StateCompareExchange(name, expected, new, post) => {
+                let prev = self.state(&name);
+                if prev == expected {
+                    self.set_name_state(&name, new);
+                    if post {
+                        self.post(&name);
+                    }
+                }
+                Ok(prev)
+            }
+            Request::StateFetchAdd(name, delta, post) => {
+                let prev = self.state(&name);
+                self.set_name_state(&name, prev.wrapping_add(delta));
+                if post {
+                    self.post(&name);
+                }
+                Ok(prev)
+            }
+            Request::Register(name) => Ok(self.register(id, name, Kind::Deliver)? as u64),
+            Request::RegisterState(name) => Ok(self.register(id, name, Kind::DeliverState)? as u64),
+            Request::RegisterCheck(name) => {
+                let token = self.register(id, name.clone(), Kind::Check)?;
+                let entry = self.names.get_mut(&name).unwrap();
+
+                entry.checks += 1;
+                if entry.slot.is_none() {
+                    entry.slot = self.free_slots.pop();
+                }
+
+                Ok(proto::pack_check(token, entry.slot.unwrap_or(shm::NO_SLOT)))
+            }
+            Request::Suspend(token) => {
+                self.token(id, token)?.suspended += 1;
+                Ok(0)
+            }
+            Request::Resume(token) => {
+                let client = self.clients.get_mut(&id).ok_or(NotifyError::Failed)?;
+                let tok = client
+                    .tokens
+                    .get_mut(&token)
+                    .ok_or(NotifyError::InvalidToken)?;
+
+                tok.suspended = tok.suspended.saturating_sub(1);
+                if tok.suspended == 0 && tok.pending {
+                    tok.pending = false;
+
+                    // Posts made while suspended are coalesced, report the latest state.
+                    let state = self.names.get(&tok.name).map_or(0, |entry| entry.state);
+                    if let Some(msg) = tok.kind.delivery(token, state) {
+                        client.send(msg);
+                    }
+                }
+                Ok(0)
+            }
+            Request::SetState(token, state) => {
+                let name = self.token(id, token)?.name.clone();
+                self.names.entry(name).or_default().state = state;
+                Ok(0)
+            }
+            Request::GetState(token) => {
+                let name = self.token(id, token)?.name.clone();
+                Ok(self.names.get(&name).map_or(0, |name| name.state))
+            }
+            Request::Check(token) => {
+                let tok = self.token(id, token)?;
+                Ok(std::mem::take(&mut tok.posted) as u64)
+            }
+            Request::Cancel(token) => {
+                let client = self.clients.get_mut(&id).ok_or(NotifyError::Failed)?;
+                let tok = client
+                    .tokens
+                    .remove(&token)
+                    .ok_or(NotifyError::InvalidToken)?;
+
+                self.release(&tok, id, token);
+                Ok(0)
+            }
+        }
+    }
+
+    fn register(&mut self, id: u64, name: String, kind: Kind) -> Result<c_int, NotifyError> {
+        let client = self.clients.get_mut(&id).ok_or(NotifyError::Failed)?;
+        let token = client.next_token;
+        client.next_token += 1;
+        client.tokens.insert(
+            token,
+            Token {
+                name: name.clone(),
+                kind,
+                suspended: 0,
+                pending: false,
+                posted: true,
+            },
+        );
+
+        self.names
+            .entry(name)
+            .or_default()
+            .tokens
+            .insert((id, token));
+        Ok(token)
+    }
+
+    /// State of `name`, 0 when nobody is registered for it.
+    fn state(&self, name: &str) -> u64 {
+        self.names.get(name).map_or(0, |entry| entry.state)
+    }
+
+    /// Set the state of `name`, which is dropped when nobody is registered for it.
+    fn set_name_state(&mut self, name: &str, state: u64) {
+        if let Some(entry) = self.names.get_mut(name) {
+            entry.state = state;
+        }
+    }
+
+    fn token(&mut self, id: u64, token: c_int) -> Result<&mut Token, NotifyError> {
+        self.clients
+            .get_mut(&id)
+            .ok_or(NotifyError::Failed)?
+            .tokens
+            .get_mut(&token)
+            .ok_or(NotifyError::InvalidToken)
+    }
+
+    fn post(&mut self, name: &str) {
+        let Some(entry) = self.names.get(name) else {
+            return;
+        };
+
+        if let (Some(shm), Some(slot)) = (&self.shm, entry.slot) {
+            shm.slot(slot).fetch_add(1, Ordering::Release);
+        }
+
+        for (id, token) in &entry.tokens {
+            let Some(client) = self.clients.get_mut(id) else {
+                continue;
+            };
+            let Some(tok) = client.tokens.get_mut(token) else {
+                continue;
+            };
+
+            tok.posted = true;
+            let Some(msg) = tok.kind.delivery(*token, entry.state) else {
+                continue;
+            };
+
+            if tok.suspended > 0 {
+                tok.pending = true;
+            } else {
+                client.send(msg);
+            }
+        }
+    }
+
+    /// Drop a token from its name, forgetting the name and its state once nobody watches it.
+    fn release(&mut self, tok: &Token, id: u64, token: c_int) {
+        let Some(entry) = self.names.get_mut(&tok.name) else {
+            return;
+        };
+
+        entry.tokens.remove(&(id, token));
+        if tok.kind == Kind::Check {
+            entry.checks -= 1;
+            if entry.checks == 0 {
+                self.free_slots.extend(entry.slot.take());
+            }
+        }
+
+        if entry.tokens.is_empty() {
+            self.names.remove(&tok.name);
+        }
+    }
+
+    fn disconnect(&mut self, id: u64) {
+        let Some(client) = self.clients.remove(&id) else {
+            return;
+        };
+
+        for (token, tok) in client.tokens {
+            self.release(&tok, id, token);
+        }
+    }
+}
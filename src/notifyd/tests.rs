@@ -0,0 +1,176 @@
+//! The server against clients running as another user, clients it can't trust and clients
+//! outliving a server restart.
+//!
+//! Switching uid needs root, those tests only run with `cargo test -- --ignored` as root.
+
+use std::io::Read;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::*;
+use crate::backend::{DaemonBackend, NotifyBackend};
+
+const NOBODY: u32 = 65534;
+
+fn socket(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "darwin-notifyd-test-{test}-{}.sock",
+        std::process::id()
+    ))
+}
+
+fn serve(path: &Path) {
+    let server = Server::bind(path).unwrap();
+    std::thread::spawn(move || server.run());
+}
+
+/// Run `f` in a child process running as `uid`, returning whether it succeeded.
+fn as_user(uid: u32, f: impl FnOnce() -> bool) -> bool {
+    match unsafe { libc::fork() } {
+        -1 => panic!("fork: {}", io::Error::last_os_error()),
+        0 => {
+            let ok = unsafe { libc::setgid(uid) == 0 && libc::setuid(uid) == 0 }
+                && std::panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(false);
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) }
+        }
+        pid => {
+            let mut status = 0;
+            assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+            libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+        }
+    }
+}
+
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[test]
+#[ignore = "needs root, run with --ignored"]
+fn other_user_is_authorized_per_name() {
+    assert!(is_root(), "switching to another uid needs root");
+
+    let path = socket("other-user");
+    serve(&path);
+
+    assert!(as_user(NOBODY, || {
+        let Ok(backend) = DaemonBackend::connect(&path) else {
+            return false;
+        };
+
+        backend.post("com.apple.tech.subcom") == Err(NotifyError::NotAuthorized)
+            && backend.post("user.uid.0.tech.subcom") == Err(NotifyError::NotAuthorized)
+            && backend.post(&format!("user.uid.{NOBODY}.tech.subcom")) == Ok(())
+            && backend.post("tech.subcom.darwin-notify") == Ok(())
+    }));
+}
+
+#[test]
+#[ignore = "needs root, run with --ignored"]
+fn other_user_is_refused_by_a_private_socket() {
+    assert!(is_root(), "switching to another uid needs root");
+
+    let path = socket("private");
+    serve(&path);
+    std::fs::set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+
+    assert!(as_user(NOBODY, || {
+        matches!(
+            DaemonBackend::connect(&path),
+            Err(NotifyError::NotAuthorized)
+        )
+    }));
+}
+
+#[test]
+fn restart_keeps_tokens() {
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const QUIET: Duration = Duration::from_millis(200);
+
+    let path = socket("restart");
+    let server = Server::bind(&path).unwrap();
+    let stopper = server.stopper();
+    let running = std::thread::spawn(move || server.run());
+
+    let (hook_tx, hook_rx) = mpsc::channel();
+    let watched = path.clone();
+    crate::set_resubscribe_hook(move |event| {
+        if event.path == watched {
+            _ = hook_tx.send(event.clone());
+        }
+    });
+
+    let backend = DaemonBackend::connect(&path).unwrap();
+    let (tx, rx) = mpsc::channel();
+    let callback = |tx: mpsc::Sender<c_int>| Box::new(move |token| _ = tx.send(token));
+    let delivered = backend
+        .register("tech.subcom.darwin-notify.restart", callback(tx.clone()))
+        .unwrap();
+    let suspended = backend
+        .register("tech.subcom.darwin-notify.restart.suspended", callback(tx))
+        .unwrap();
+    backend.suspend(suspended).unwrap();
+    let checked = backend
+        .register_check("tech.subcom.darwin-notify.restart")
+        .unwrap();
+    let poller = backend.check_poller(checked).unwrap();
+    assert_eq!(poller.poll(), Some(true));
+
+    stopper.stop();
+    running.join().unwrap().unwrap();
+    assert!(!path.exists());
+    serve(&path);
+
+    let event = hook_rx.recv_timeout(TIMEOUT).unwrap();
+    crate::clear_resubscribe_hook();
+    assert!(event.tokens.contains(&delivered));
+    assert!(event.tokens.contains(&suspended));
+    assert!(event.tokens.contains(&checked));
+    assert!(event.failed.is_empty());
+
+    // The poller handed out before follows the counter of the new server.
+    assert_eq!(poller.poll(), Some(true));
+    assert_eq!(poller.poll(), Some(false));
+    backend.post("tech.subcom.darwin-notify.restart").unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT), Ok(delivered));
+    assert_eq!(poller.poll(), Some(true));
+
+    backend
+        .post("tech.subcom.darwin-notify.restart.suspended")
+        .unwrap();
+    assert!(rx.recv_timeout(QUIET).is_err());
+    backend.resume(suspended).unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT), Ok(suspended));
+}
+
+#[test]
+fn names_are_validated() {
+    let path = socket("names");
+    serve(&path);
+
+    let backend = DaemonBackend::connect(&path).unwrap();
+    let long = "n".repeat(60 * 1024);
+    assert_eq!(backend.register_check(""), Err(NotifyError::InvalidName));
+    assert_eq!(backend.register_check(&long), Err(NotifyError::InvalidName));
+    assert_eq!(backend.post("tech\0subcom"), Err(NotifyError::InvalidName));
+}
+
+#[test]
+fn client_not_reading_is_disconnected() {
+    let path = socket("not-reading");
+    serve(&path);
+
+    let mut idle = UnixStream::connect(&path).unwrap();
+    let register = Request::Register("tech.subcom.darwin-notify.idle".into());
+    proto::write_frame(&mut idle, &register.encode(1)).unwrap();
+
+    let backend = DaemonBackend::connect(&path).unwrap();
+    for _ in 0..QUEUE_LEN * 16 {
+        backend.post("tech.subcom.darwin-notify.idle").unwrap();
+    }
+
+    // Reads what was written before the server gave up, then the end of the stream.
+    idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    idle.read_to_end(&mut Vec::new()).unwrap();
+}
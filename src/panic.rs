@@ -0,0 +1,199 @@
+use std::any::Any;
+use std::ffi::c_int;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::backend::{Callback, StateCallback};
+use crate::Token;
+
+/// What to do after a notification callback panicked.
+///
+/// The panic is always caught so it never unwinds into the C dispatch machinery, then reported
+/// to the hook installed with [set_panic_hook].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Keep the registration, logging the panic when the `tracing` feature is enabled.
+    #[default]
+    Log,
+    /// Cancel the registration, no further callbacks run and the token becomes invalid.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::Arc;
+    /// use darwin_notify::backend::{self, MockBackend};
+    /// use darwin_notify::{NotifyError, PanicPolicy, RegisterOptions};
+    ///
+    /// backend::with_backend(Arc::new(MockBackend::new()), || {
+    ///     let options = RegisterOptions::new().panic_policy(PanicPolicy::Cancel);
+    ///     let subscription =
+    ///         darwin_notify::notify_register_with("tech.subcom.darwin-notify", &options, |_| {
+    ///             panic!("oops")
+    ///         })
+    ///         .unwrap();
+    ///
+    ///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+    ///     assert!(!subscription.token().is_valid());
+    ///     assert_eq!(subscription.cancel().unwrap_err(), NotifyError::InvalidToken);
+    /// });
+    /// ```
+    Cancel,
+    /// Abort the process.
+    Abort,
+}
+
+/// A panic caught in a notification callback.
+#[derive(Debug, Clone)]
+pub struct CallbackPanic {
+    /// Name the callback was registered for.
+    pub name: String,
+    /// Token the notification was delivered to.
+    pub token: c_int,
+    /// The panic message, if the payload was a string.
+    pub message: Option<String>,
+}
+
+type Hook = Arc<dyn Fn(&CallbackPanic) + Send + Sync>;
+
+static POLICY: RwLock<PanicPolicy> = RwLock::new(PanicPolicy::Log);
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Set the policy used by registrations that don't choose one with
+/// [RegisterOptions::panic_policy](crate::RegisterOptions::panic_policy).
+pub fn set_panic_policy(policy: PanicPolicy) {
+    *POLICY.write().unwrap_or_else(|e| e.into_inner()) = policy;
+}
+
+/// The policy used by registrations that don't choose one.
+pub fn panic_policy() -> PanicPolicy {
+    *POLICY.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Call `hook` with every panic caught in a notification callback, before the policy applies.
+///
+/// # Example
+/// ```
+/// use std::sync::{mpsc, Arc, Mutex};
+/// use darwin_notify::backend::{self, MockBackend};
+///
+/// let (tx, rx) = mpsc::channel();
+/// let tx = Mutex::new(tx);
+/// darwin_notify::set_panic_hook(move |panic| {
+///     tx.lock().unwrap().send(panic.message.clone()).unwrap();
+/// });
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let _subscription =
+///         darwin_notify::notify_register("tech.subcom.darwin-notify", |_| panic!("oops")).unwrap();
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+/// });
+///
+/// assert_eq!(rx.recv().unwrap().as_deref(), Some("oops"));
+/// ```
+pub fn set_panic_hook(hook: impl Fn(&CallbackPanic) + Send + Sync + 'static) {
+    *HOOK.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(hook));
+}
+
+/// Remove the hook installed with [set_panic_hook].
+pub fn clear_panic_hook() {
+    *HOOK.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// The token of a guarded callback, known once the registration returned.
+#[derive(Default)]
+pub(crate) struct TokenSlot {
+    token: OnceLock<Token>,
+    cancel: AtomicBool,
+}
+
+impl TokenSlot {
+    /// Set the token, cancelling it if the callback already panicked.
+    pub fn set(&self, token: Token) {
+        _ = self.token.set(token);
+
+        if self.cancel.load(Ordering::SeqCst) {
+            _ = crate::notify_cancel(token);
+        }
+    }
+
+    /// Cancel the token, now or once it is set.
+    fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+
+        // Cancelling twice when racing with set fails the second time, the token is forgotten.
+        if let Some(token) = self.token.get() {
+            _ = crate::notify_cancel(*token);
+        }
+    }
+}
+
+/// Wrap `cb` so panics are caught and handled according to `policy`.
+pub(crate) fn guard(
+    name: &str,
+    policy: Option<PanicPolicy>,
+    slot: Arc<TokenSlot>,
+    cb: Callback,
+) -> Callback {
+    let name = name.to_owned();
+
+    Box::new(move |token| catch(&name, policy, &slot, token, || cb(token)))
+}
+
+/// [guard] for callbacks receiving the state of the name.
+pub(crate) fn guard_state(
+    name: &str,
+    policy: Option<PanicPolicy>,
+    slot: Arc<TokenSlot>,
+    cb: StateCallback,
+) -> StateCallback {
+    let name = name.to_owned();
+
+    Box::new(move |token, state| catch(&name, policy, &slot, token, || cb(token, state)))
+}
+
+fn catch(
+    name: &str,
+    policy: Option<PanicPolicy>,
+    slot: &TokenSlot,
+    token: c_int,
+    f: impl FnOnce(),
+) {
+    let Err(payload) = std::panic::catch_unwind(AssertUnwindSafe(f)) else {
+        return;
+    };
+
+    let panic = CallbackPanic {
+        name: name.to_owned(),
+        token,
+        message: message(&*payload),
+    };
+
+    #[cfg(feature = "tracing")]
+    tracing::error!(
+        "darwin-notify: callback for {} (token {}) panicked: {}",
+        panic.name,
+        panic.token,
+        panic.message.as_deref().unwrap_or("<non-string payload>")
+    );
+
+    let hook = HOOK.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(hook) = hook {
+        // A panicking hook must not unwind into C either.
+        _ = std::panic::catch_unwind(AssertUnwindSafe(|| hook(&panic)));
+    }
+
+    match policy.unwrap_or_else(panic_policy) {
+        PanicPolicy::Log => {}
+        // Through the tracked token, so the subscription sees it is gone.
+        PanicPolicy::Cancel => slot.cancel(),
+        PanicPolicy::Abort => std::process::abort(),
+    }
+}
+
+fn message(payload: &(dyn Any + Send)) -> Option<String> {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        return Some((*msg).to_owned());
+    }
+
+    payload.downcast_ref::<String>().cloned()
+}
@@ -0,0 +1,292 @@
+//! Wire format spoken between [DaemonBackend](crate::backend::DaemonBackend) and the
+//! [notifyd](crate::notifyd) server.
+//!
+//! Every message is a frame made of a little endian `u32` length followed by that many bytes.
+//! Client frames carry a sequence number and a [Request], server frames are either a [Reply] to
+//! the request with the same sequence number or an unsolicited delivery of a posted token.
+//! Status codes are the `NOTIFY_STATUS_*` values [NotifyError](crate::NotifyError) mirrors.
+
+use std::ffi::c_int;
+use std::io::{self, Read, Write};
+
+/// Frames larger than this are rejected, names are the only variable sized field.
+const MAX_FRAME: usize = 64 * 1024;
+
+const REQ_POST: u8 = 0;
+const REQ_REGISTER: u8 = 1;
+const REQ_SUSPEND: u8 = 2;
+const REQ_RESUME: u8 = 3;
+const REQ_SET_STATE: u8 = 4;
+const REQ_GET_STATE: u8 = 5;
+const REQ_CHECK: u8 = 6;
+const REQ_CANCEL: u8 = 7;
+const REQ_REGISTER_CHECK: u8 = 8;
+const REQ_POST_STATE: u8 = 9;
+const REQ_STATE_CAS: u8 = 10;
+const REQ_STATE_ADD: u8 = 11;
+const REQ_REGISTER_STATE: u8 = 12;
+
+const MSG_REPLY: u8 = 0;
+const MSG_DELIVER: u8 = 1;
+const MSG_DELIVER_STATE: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Request {
+    Post(String),
+    Register(String),
+    Suspend(c_int),
+    Resume(c_int),
+    SetState(c_int, u64),
+    GetState(c_int),
+    Check(c_int),
+    Cancel(c_int),
+    /// Replies with [pack_check] of the token and its [shm](crate::shm) slot.
+    RegisterCheck(String),
+    /// Set the state of a name and post it, in one step.
+    PostState(String, u64),
+    /// `(name, expected, new, post)`, replies with the previous state.
+    StateCompareExchange(String, u64, u64, bool),
+    /// `(name, delta, post)`, replies with the previous state.
+    StateFetchAdd(String, u64, bool),
+    /// Like [Request::Register], deliveries carry the state of the name at post time.
+    RegisterState(String),
+}
+
+/// Answer to a [Request], `value` holds the token, state or check result on success.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Reply {
+    pub seq: u32,
+    pub status: u32,
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Message {
+    Reply(Reply),
+    Deliver(c_int),
+    DeliverState(c_int, u64),
+}
+
+impl Request {
+    pub fn encode(&self, seq: u32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_u32(&mut buf, seq);
+
+        match self {
+            Self::Post(name) => {
+                buf.push(REQ_POST);
+                put_str(&mut buf, name);
+            }
+            Self::Register(name) => {
+                buf.push(REQ_REGISTER);
+                put_str(&mut buf, name);
+            }
+            Self::Suspend(token) => {
+                buf.push(REQ_SUSPEND);
+                put_i32(&mut buf, *token);
+            }
+            Self::Resume(token) => {
+                buf.push(REQ_RESUME);
+                put_i32(&mut buf, *token);
+            }
+            Self::SetState(token, state) => {
+                buf.push(REQ_SET_STATE);
+                put_i32(&mut buf, *token);
+                put_u64(&mut buf, *state);
+            }
+            Self::GetState(token) => {
+                buf.push(REQ_GET_STATE);
+                put_i32(&mut buf, *token);
+            }
+            Self::Check(token) => {
+                buf.push(REQ_CHECK);
+                put_i32(&mut buf, *token);
+            }
+            Self::Cancel(token) => {
+                buf.push(REQ_CANCEL);
+                put_i32(&mut buf, *token);
+            }
+            Self::RegisterCheck(name) => {
+                buf.push(REQ_REGISTER_CHECK);
+                put_str(&mut buf, name);
+            }
+            Self::PostState(name, state) => {
+                buf.push(REQ_POST_STATE);
+                put_str(&mut buf, name);
+                put_u64(&mut buf, *state);
+            }
+            Self::StateCompareExchange(name, expected, new, post) => {
+                buf.push(REQ_STATE_CAS);
+                put_str(&mut buf, name);
+                put_u64(&mut buf, *expected);
+                put_u64(&mut buf, *new);
+                buf.push(*post as u8);
+            }
+            Self::StateFetchAdd(name, delta, post) => {
+                buf.push(REQ_STATE_ADD);
+                put_str(&mut buf, name);
+                put_u64(&mut buf, *delta);
+                buf.push(*post as u8);
+            }
+            Self::RegisterState(name) => {
+                buf.push(REQ_REGISTER_STATE);
+                put_str(&mut buf, name);
+            }
+        }
+
+        buf
+    }
+
+    pub fn decode(frame: &[u8]) -> io::Result<(u32, Self)> {
+        let mut r = Cursor(frame);
+        let seq = r.u32()?;
+
+        let req = match r.u8()? {
+            REQ_POST => Self::Post(r.string()?),
+            REQ_REGISTER => Self::Register(r.string()?),
+            REQ_SUSPEND => Self::Suspend(r.i32()?),
+            REQ_RESUME => Self::Resume(r.i32()?),
+            REQ_SET_STATE => Self::SetState(r.i32()?, r.u64()?),
+            REQ_GET_STATE => Self::GetState(r.i32()?),
+            REQ_CHECK => Self::Check(r.i32()?),
+            REQ_CANCEL => Self::Cancel(r.i32()?),
+            REQ_REGISTER_CHECK => Self::RegisterCheck(r.string()?),
+            REQ_POST_STATE => Self::PostState(r.string()?, r.u64()?),
+            REQ_STATE_CAS => {
+                Self::StateCompareExchange(r.string()?, r.u64()?, r.u64()?, r.u8()? != 0)
+            }
+            REQ_STATE_ADD => Self::StateFetchAdd(r.string()?, r.u64()?, r.u8()? != 0),
+            REQ_REGISTER_STATE => Self::RegisterState(r.string()?),
+            op => return Err(invalid(format!("unknown request {op}"))),
+        };
+
+        Ok((seq, req))
+    }
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        match self {
+            Self::Reply(reply) => {
+                buf.push(MSG_REPLY);
+                put_u32(&mut buf, reply.seq);
+                put_u32(&mut buf, reply.status);
+                put_u64(&mut buf, reply.value);
+            }
+            Self::Deliver(token) => {
+                buf.push(MSG_DELIVER);
+                put_i32(&mut buf, *token);
+            }
+            Self::DeliverState(token, state) => {
+                buf.push(MSG_DELIVER_STATE);
+                put_i32(&mut buf, *token);
+                put_u64(&mut buf, *state);
+            }
+        }
+
+        buf
+    }
+
+    pub fn decode(frame: &[u8]) -> io::Result<Self> {
+        let mut r = Cursor(frame);
+
+        match r.u8()? {
+            MSG_REPLY => Ok(Self::Reply(Reply {
+                seq: r.u32()?,
+                status: r.u32()?,
+                value: r.u64()?,
+            })),
+            MSG_DELIVER => Ok(Self::Deliver(r.i32()?)),
+            MSG_DELIVER_STATE => Ok(Self::DeliverState(r.i32()?, r.u64()?)),
+            tag => Err(invalid(format!("unknown message {tag}"))),
+        }
+    }
+}
+
+/// Reply value of [Request::RegisterCheck].
+pub(crate) fn pack_check(token: c_int, slot: u32) -> u64 {
+    (slot as u64) << 32 | token as u32 as u64
+}
+
+pub(crate) fn unpack_check(value: u64) -> (c_int, u32) {
+    (value as u32 as c_int, (value >> 32) as u32)
+}
+
+pub(crate) fn read_frame(r: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    r.read_exact(&mut len)?;
+
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(invalid(format!("frame of {len} bytes is too large")));
+    }
+
+    let mut frame = vec![0; len];
+    r.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+pub(crate) fn write_frame(w: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(4 + frame.len());
+    put_u32(&mut buf, frame.len() as u32);
+    buf.extend_from_slice(frame);
+    w.write_all(&buf)
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_i32(buf: &mut Vec<u8>, v: i32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_u32(buf, s.len() as u32);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid("truncated frame".into()));
+        }
+
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+
+use crate::backend::Callback;
+use crate::{sys, PanicPolicy};
+
+/// A unit of work handed to an [Executor].
+pub type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs callbacks registered with [RegisterOptions::executor].
+///
+/// Implemented for any `Fn(Job) + Send + Sync` closure.
+pub trait Executor: Send + Sync {
+    /// Run `job`, now or later, on any thread.
+    fn execute(&self, job: Job);
+}
+
+impl<F> Executor for F
+where
+    F: Fn(Job) + Send + Sync,
+{
+    fn execute(&self, job: Job) {
+        self(job)
+    }
+}
+
+/// Quality of service class of a global queue, mirrors `qos_class_t`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum Qos {
+    UserInteractive = sys::QOS_CLASS_USER_INTERACTIVE,
+    UserInitiated = sys::QOS_CLASS_USER_INITIATED,
+    #[default]
+    Default = sys::QOS_CLASS_DEFAULT,
+    Utility = sys::QOS_CLASS_UTILITY,
+    Background = sys::QOS_CLASS_BACKGROUND,
+}
+
+/// Where the callbacks of a registration run.
+///
+/// | Option | macOS | elsewhere |
+/// |---|---|---|
+/// | default | current dispatch queue | backend's own thread |
+/// | [main_queue](RegisterOptions::main_queue) | main dispatch queue | thread running a [NotifyLoop](crate::NotifyLoop) |
+/// | [global_queue](RegisterOptions::global_queue) | global queue of the [Qos] | shared thread pool, QoS is ignored |
+/// | [serial_queue](RegisterOptions::serial_queue) | serial queue with that label | dedicated thread with that name |
+/// | [executor](RegisterOptions::executor) | the [Executor] | the [Executor] |
+///
+/// Serial queues are created on first use and shared by every registration using the same label.
+///
+/// # Threading
+/// Callbacks must be `Send`, every target may run them on a thread other than the one that
+/// registered. Whether they must also be `Sync` depends on the registration function:
+///
+/// | Function | Callback | Guarantee |
+/// |---|---|---|
+/// | [notify_register_with](crate::notify_register_with) | `FnMut + Send` | calls never overlap, on any target |
+/// | [notify_register_concurrent](crate::notify_register_concurrent) | `Fn + Send + Sync` | calls may overlap on the global queues, the default queue and executors |
+///
+/// Serial callbacks delivered on a concurrent target wait for the running call to return.
+///
+/// # Example
+/// ```
+/// use std::sync::{mpsc, Arc};
+/// use darwin_notify::backend::{self, MockBackend};
+/// use darwin_notify::RegisterOptions;
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let (tx, rx) = mpsc::channel();
+///     let options = RegisterOptions::new().serial_queue("tech.subcom.darwin-notify.queue");
+///     let _subscription =
+///         darwin_notify::notify_register_with("tech.subcom.darwin-notify", &options, move |_| {
+///             tx.send(std::thread::current().name().map(String::from)).unwrap();
+///         })
+///         .unwrap();
+///
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+///     assert!(rx.recv().unwrap().is_some());
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct RegisterOptions {
+    pub(crate) target: Target,
+    pub(crate) panic_policy: Option<PanicPolicy>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) enum Target {
+    #[default]
+    Current,
+    Main,
+    // Only libdispatch has QoS classes, other backends ignore it.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    Global(Qos),
+    Serial(String),
+    Executor(Arc<dyn Executor>),
+}
+
+impl RegisterOptions {
+    /// Options delivering on the default queue of the backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver on the main queue.
+    pub fn main_queue(mut self) -> Self {
+        self.target = Target::Main;
+        self
+    }
+
+    /// Deliver on the global concurrent queue of `qos`.
+    pub fn global_queue(mut self, qos: Qos) -> Self {
+        self.target = Target::Global(qos);
+        self
+    }
+
+    /// Deliver on the serial queue named `label`, created on first use.
+    pub fn serial_queue(mut self, label: impl Into<String>) -> Self {
+        self.target = Target::Serial(label.into());
+        self
+    }
+
+    /// Hand every delivery to `executor`.
+    pub fn executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.target = Target::Executor(executor);
+        self
+    }
+
+    /// Handle panics of the callback with `policy` instead of the global [panic_policy].
+    ///
+    /// [panic_policy]: crate::panic_policy
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = Some(policy);
+        self
+    }
+
+    /// Wrap `cb` so every call is handed to the target instead of running on the backend's thread.
+    pub(crate) fn wrap(&self, cb: Callback) -> Callback {
+        match &self.target {
+            Target::Current => cb,
+            Target::Main => hop(cb, crate::runloop::dispatch_main),
+            Target::Global(_) => hop(cb, |job| pool().execute(job)),
+            Target::Serial(label) => {
+                let queue = serial(label);
+                hop(cb, move |job| _ = queue.send(job))
+            }
+            Target::Executor(executor) => {
+                let executor = executor.clone();
+                hop(cb, move |job| executor.execute(job))
+            }
+        }
+    }
+}
+
+fn hop(cb: Callback, spawn: impl Fn(Job) + Send + Sync + 'static) -> Callback {
+    let cb = Arc::new(cb);
+
+    Box::new(move |token: c_int| {
+        let cb = cb.clone();
+        spawn(Box::new(move || cb(token)))
+    })
+}
+
+/// Turn `cb` into a [Callback] whose calls never overlap, later calls wait for the running one.
+pub(crate) fn serialize<F>(cb: F) -> Callback
+where
+    F: FnMut(c_int) + Send + 'static,
+{
+    let cb = Mutex::new(cb);
+
+    Box::new(move |token| (cb.lock().unwrap_or_else(|e| e.into_inner()))(token))
+}
+
+/// Worker threads emulating the global queues.
+struct Pool {
+    tx: Mutex<mpsc::Sender<Job>>,
+}
+
+impl Pool {
+    fn execute(&self, job: Job) {
+        _ = self.tx.lock().unwrap_or_else(|e| e.into_inner()).send(job);
+    }
+}
+
+fn pool() -> &'static Pool {
+    static POOL: OnceLock<Pool> = OnceLock::new();
+
+    POOL.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        let workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+
+        for i in 0..workers {
+            let rx = rx.clone();
+            spawn(format!("darwin-notify-{i}"), move || loop {
+                let job = rx.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => break,
+                }
+            });
+        }
+
+        Pool { tx: Mutex::new(tx) }
+    })
+}
+
+/// The thread emulating the serial queue `label`.
+fn serial(label: &str) -> mpsc::Sender<Job> {
+    static QUEUES: OnceLock<Mutex<HashMap<String, mpsc::Sender<Job>>>> = OnceLock::new();
+
+    QUEUES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(label.into())
+        .or_insert_with(|| {
+            let (tx, rx) = mpsc::channel::<Job>();
+            spawn(label.into(), move || rx.into_iter().for_each(|job| job()));
+            tx
+        })
+        .clone()
+}
+
+pub(crate) fn spawn(name: String, f: impl FnOnce() + Send + 'static) {
+    std::thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            #[cfg(unix)]
+            crate::signal::block_all();
+            f()
+        })
+        .expect("darwin-notify: failed to spawn a queue thread");
+}
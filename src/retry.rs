@@ -0,0 +1,185 @@
+use std::collections::hash_map::RandomState;
+use std::ffi::c_int;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::NResult;
+
+/// How posts are retried when they fail with a [retryable](crate::NotifyError::is_retryable)
+/// error, for example while the [notifyd](crate::notifyd) server restarts.
+///
+/// Applies to [notify_post](crate::notify_post) and [post_with_state](crate::post_with_state).
+/// The default makes a single attempt.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use darwin_notify::backend::{self, MockBackend};
+/// use darwin_notify::{NotifyError, RetryPolicy};
+///
+/// darwin_notify::set_retry_policy(
+///     RetryPolicy::new(100)
+///         .backoff(Duration::from_millis(1))
+///         .max_backoff(Duration::from_millis(10))
+///         .jitter(0.5),
+/// );
+///
+/// let mock = Arc::new(MockBackend::new());
+/// mock.fail("tech.subcom.darwin-notify", NotifyError::ServerNotFound);
+///
+/// let restart = mock.clone();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(Duration::from_millis(20));
+///     restart.clear_failure("tech.subcom.darwin-notify");
+/// });
+///
+/// backend::with_backend(mock.clone(), || {
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+/// });
+/// assert_eq!(mock.posts(), ["tech.subcom.darwin-notify"]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Make a single attempt.
+    pub const NEVER: Self = Self {
+        attempts: 1,
+        backoff: Duration::from_millis(50),
+        max_backoff: Duration::from_secs(2),
+        jitter: 0.0,
+    };
+
+    /// Make up to `attempts` attempts in total, waiting 50ms before the first retry and twice as
+    /// long before every next one, up to 2s.
+    pub fn new(attempts: u32) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            ..Self::NEVER
+        }
+    }
+
+    /// Wait `backoff` before the first retry.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Never wait longer than `max` between attempts.
+    pub fn max_backoff(mut self, max: Duration) -> Self {
+        self.max_backoff = max;
+        self
+    }
+
+    /// Lengthen or shorten every wait by a random fraction of it, up to `jitter` between 0 and 1,
+    /// so clients don't retry in lockstep.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Attempts in total, including the first one.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Wait before retry number `retry`, counting from 0.
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+
+        // Uniform enough for spreading retries, without a dependency on a random number crate.
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 + self.jitter * (2.0 * random - 1.0))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::NEVER
+    }
+}
+
+static POLICY: RwLock<RetryPolicy> = RwLock::new(RetryPolicy::NEVER);
+
+/// Set the policy posts are retried with.
+pub fn set_retry_policy(policy: RetryPolicy) {
+    *POLICY.write().unwrap_or_else(|e| e.into_inner()) = policy;
+}
+
+/// The policy posts are retried with.
+pub fn retry_policy() -> RetryPolicy {
+    *POLICY.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Call `f` until it succeeds, fails with an error that isn't retryable or runs out of attempts.
+pub(crate) fn retry<T>(mut f: impl FnMut() -> NResult<T>) -> NResult<T> {
+    let policy = retry_policy();
+
+    let mut retry = 0;
+    loop {
+        match f() {
+            Err(err) if err.is_retryable() && retry + 1 < policy.attempts => {
+                std::thread::sleep(policy.delay(retry));
+                retry += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Registrations of a [DaemonBackend](crate::backend::DaemonBackend) registered again after it
+/// reconnected to the server.
+#[derive(Debug, Clone)]
+pub struct Resubscribed {
+    /// Socket of the server.
+    pub path: PathBuf,
+    /// Tokens registered again, they keep their value.
+    pub tokens: Vec<c_int>,
+    /// Tokens the server refused to register again, they are now invalid.
+    pub failed: Vec<c_int>,
+}
+
+type Hook = Arc<dyn Fn(&Resubscribed) + Send + Sync>;
+
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Call `hook` every time a [DaemonBackend](crate::backend::DaemonBackend) reconnected to the
+/// server and registered its live tokens again.
+///
+/// The hook runs on a thread of the backend, or on the thread whose request reconnected it.
+pub fn set_resubscribe_hook(hook: impl Fn(&Resubscribed) + Send + Sync + 'static) {
+    *HOOK.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(hook));
+}
+
+/// Remove the hook installed with [set_resubscribe_hook].
+pub fn clear_resubscribe_hook() {
+    *HOOK.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Report a resubscription to the hook.
+#[cfg(unix)]
+pub(crate) fn resubscribed(event: &Resubscribed) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        "darwin-notify: reconnected to {}, {} tokens registered again, {} failed",
+        event.path.display(),
+        event.tokens.len(),
+        event.failed.len()
+    );
+
+    let hook = HOOK.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(hook) = hook {
+        _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| hook(event)));
+    }
+}
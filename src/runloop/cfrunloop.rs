@@ -0,0 +1,72 @@
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use super::Job;
+use crate::sys;
+
+/// How long to back off when the run loop has no sources and returns straight away.
+const IDLE: Duration = Duration::from_millis(10);
+
+/// A year, the longest single run of the `CFRunLoop` when running without a timeout.
+const FOREVER: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+pub(super) struct Loop {
+    rl: sys::CFRunLoopRef,
+    // Bound to the thread owning the run loop.
+    _thread: PhantomData<*const ()>,
+}
+
+#[derive(Clone)]
+pub(super) struct Waker(sys::CFRunLoopRef);
+
+// CFRunLoopStop and CFRunLoopWakeUp may be called from any thread.
+unsafe impl Send for Waker {}
+unsafe impl Sync for Waker {}
+
+impl Loop {
+    pub fn new() -> Self {
+        Self {
+            rl: unsafe { sys::CFRunLoopGetCurrent() },
+            _thread: PhantomData,
+        }
+    }
+
+    pub fn waker(&self) -> Waker {
+        Waker(self.rl)
+    }
+
+    /// Run the loop until something happens or `timeout` elapses.
+    pub fn turn(&self, timeout: Option<Duration>) {
+        let seconds = timeout.map_or(FOREVER, |timeout| timeout.as_secs_f64());
+
+        let res = unsafe { sys::CFRunLoopRunInMode(sys::kCFRunLoopDefaultMode, seconds, 1) };
+        if res as u32 == sys::kCFRunLoopRunFinished {
+            std::thread::sleep(timeout.map_or(IDLE, |timeout| timeout.min(IDLE)));
+        }
+    }
+}
+
+impl Waker {
+    pub fn wake(&self) {
+        unsafe {
+            sys::CFRunLoopStop(self.0);
+            sys::CFRunLoopWakeUp(self.0);
+        }
+    }
+}
+
+pub(crate) fn dispatch_main(job: Job) {
+    extern "C" fn run(ctx: *mut c_void) {
+        let job = unsafe { Box::from_raw(ctx as *mut Job) };
+        job()
+    }
+
+    unsafe {
+        sys::dispatch_async_f(
+            sys::main_queue(),
+            Box::into_raw(Box::new(job)) as _,
+            Some(run),
+        )
+    }
+}
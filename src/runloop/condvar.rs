@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::Job;
+
+/// The emulated main queue, drained by whichever thread runs a loop.
+#[derive(Default)]
+struct MainQueue {
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: VecDeque<Job>,
+}
+
+pub(super) struct Loop {
+    /// Set under the main queue lock, so a wake up can't slip in before the wait.
+    woken: Arc<AtomicBool>,
+    // Bound to the thread that created it, like the CFRunLoop of macOS.
+    _thread: PhantomData<*const ()>,
+}
+
+#[derive(Clone)]
+pub(super) struct Waker(Arc<AtomicBool>);
+
+fn main_queue() -> &'static MainQueue {
+    static MAIN: std::sync::OnceLock<MainQueue> = std::sync::OnceLock::new();
+    MAIN.get_or_init(Default::default)
+}
+
+fn lock(main: &MainQueue) -> MutexGuard<'_, State> {
+    main.state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Loop {
+    pub fn new() -> Self {
+        Self {
+            woken: Default::default(),
+            _thread: PhantomData,
+        }
+    }
+
+    pub fn waker(&self) -> Waker {
+        Waker(self.woken.clone())
+    }
+
+    /// Wait for jobs or a wake up for at most `timeout`, then run the queued jobs.
+    pub fn turn(&self, timeout: Option<Duration>) {
+        let main = main_queue();
+        let mut state = lock(main);
+
+        if state.jobs.is_empty() && !self.woken.load(Ordering::Relaxed) {
+            state = match timeout {
+                Some(timeout) => {
+                    main.ready
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => main.ready.wait(state).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+        self.woken.store(false, Ordering::Relaxed);
+
+        while let Some(job) = state.jobs.pop_front() {
+            drop(state);
+            job();
+            state = lock(main);
+        }
+    }
+}
+
+impl Waker {
+    pub fn wake(&self) {
+        let main = main_queue();
+        let _state = lock(main);
+        self.0.store(true, Ordering::Relaxed);
+        main.ready.notify_all();
+    }
+}
+
+pub(crate) fn dispatch_main(job: Job) {
+    let main = main_queue();
+    lock(main).jobs.push_back(job);
+    main.ready.notify_all();
+}
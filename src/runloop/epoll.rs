@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+use std::io;
+use std::marker::PhantomData;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use super::Job;
+
+/// The emulated main queue, drained by whichever thread runs a loop.
+struct MainQueue {
+    jobs: Mutex<VecDeque<Job>>,
+    ready: OwnedFd,
+}
+
+pub(super) struct Loop {
+    epoll: OwnedFd,
+    wake: Arc<OwnedFd>,
+    // Bound to the thread that created it, like the CFRunLoop of macOS.
+    _thread: PhantomData<*const ()>,
+}
+
+#[derive(Clone)]
+pub(super) struct Waker(Arc<OwnedFd>);
+
+fn main_queue() -> &'static MainQueue {
+    static MAIN: OnceLock<MainQueue> = OnceLock::new();
+
+    MAIN.get_or_init(|| MainQueue {
+        jobs: Default::default(),
+        ready: eventfd().expect("darwin-notify: failed to create the main queue eventfd"),
+    })
+}
+
+fn eventfd() -> io::Result<OwnedFd> {
+    match unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) } {
+        -1 => Err(io::Error::last_os_error()),
+        fd => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+    }
+}
+
+fn signal(fd: &OwnedFd) {
+    let one = 1u64;
+    unsafe { libc::write(fd.as_raw_fd(), &one as *const u64 as _, 8) };
+}
+
+fn drain(fd: &OwnedFd) {
+    let mut count = 0u64;
+    unsafe { libc::read(fd.as_raw_fd(), &mut count as *mut u64 as _, 8) };
+}
+
+impl Loop {
+    pub fn new() -> Self {
+        Self::try_new().expect("darwin-notify: failed to create the notify loop epoll set")
+    }
+
+    fn try_new() -> io::Result<Self> {
+        let epoll = match unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) } {
+            -1 => return Err(io::Error::last_os_error()),
+            fd => unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+        let wake = eventfd()?;
+
+        for fd in [&main_queue().ready, &wake] {
+            let mut event = libc::epoll_event {
+                events: libc::EPOLLIN as u32,
+                u64: fd.as_raw_fd() as u64,
+            };
+
+            if unsafe {
+                libc::epoll_ctl(
+                    epoll.as_raw_fd(),
+                    libc::EPOLL_CTL_ADD,
+                    fd.as_raw_fd(),
+                    &mut event,
+                )
+            } == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(Self {
+            epoll,
+            wake: Arc::new(wake),
+            _thread: PhantomData,
+        })
+    }
+
+    pub fn waker(&self) -> Waker {
+        Waker(self.wake.clone())
+    }
+
+    /// Wait for jobs or a wake up for at most `timeout`, then run the queued jobs.
+    pub fn turn(&self, timeout: Option<Duration>) {
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_millis().clamp(1, i32::MAX as u128) as i32
+        });
+
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; 2];
+        let ready =
+            unsafe { libc::epoll_wait(self.epoll.as_raw_fd(), events.as_mut_ptr(), 2, timeout) };
+
+        for event in events.iter().take(ready.max(0) as usize) {
+            if event.u64 == self.wake.as_raw_fd() as u64 {
+                drain(&self.wake);
+            }
+        }
+
+        let main = main_queue();
+        drain(&main.ready);
+
+        loop {
+            let job = main
+                .jobs
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .pop_front();
+            match job {
+                Some(job) => job(),
+                None => break,
+            }
+        }
+    }
+}
+
+impl Waker {
+    pub fn wake(&self) {
+        signal(&self.0);
+    }
+}
+
+pub(crate) fn dispatch_main(job: Job) {
+    let main = main_queue();
+    main.jobs
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push_back(job);
+    signal(&main.ready);
+}
@@ -0,0 +1,178 @@
+//! The loop main queue callbacks are delivered on.
+//!
+//! On macOS [NotifyLoop] drives the `CFRunLoop` of the current thread, which also drains the main
+//! dispatch queue when that thread is the main thread. On Linux it waits on an `epoll` set for
+//! jobs queued to the emulated main queue. Elsewhere it waits on a condition variable.
+//!
+//! Outside macOS only main queue jobs run on the loop: [NotifyLoop::dispatch] and callbacks
+//! registered with [RegisterOptions::main_queue](crate::RegisterOptions::main_queue). The
+//! emulation backends run every other callback on their own dispatcher threads.
+//!
+//! A [NotifyLoop] stays on the thread that created it on every platform, like the `CFRunLoop` it
+//! wraps on macOS.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "macos")]
+mod cfrunloop;
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+mod condvar;
+#[cfg(target_os = "linux")]
+mod epoll;
+
+#[cfg(target_os = "macos")]
+use cfrunloop as imp;
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+use condvar as imp;
+#[cfg(target_os = "linux")]
+use epoll as imp;
+
+pub(crate) use imp::dispatch_main;
+
+use crate::queue::Job;
+
+/// How long [NotifyLoop::run_until] waits before evaluating its predicate again.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs the main queue until told to stop.
+///
+/// Replaces calling `CFRunLoopRun` directly: the loop can be stopped from any thread through a
+/// [Stopper], run for a bounded time or until a condition holds, and works outside macOS.
+///
+/// # Example
+/// ```
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use darwin_notify::NotifyLoop;
+///
+/// let notify_loop = NotifyLoop::new();
+/// let stopper = notify_loop.stopper();
+/// std::thread::spawn(move || stopper.stop());
+/// notify_loop.run();
+///
+/// let done = Arc::new(AtomicBool::new(false));
+/// let flag = done.clone();
+/// NotifyLoop::dispatch(move || flag.store(true, Ordering::SeqCst));
+/// assert!(notify_loop.run_until(|| done.load(Ordering::SeqCst)));
+///
+/// notify_loop.run_for(Duration::from_millis(10));
+/// ```
+///
+/// A loop can't be moved to another thread, a [Stopper] can:
+/// ```compile_fail
+/// let notify_loop = darwin_notify::NotifyLoop::new();
+/// std::thread::spawn(move || notify_loop.run());
+/// ```
+pub struct NotifyLoop {
+    imp: imp::Loop,
+    stopped: Arc<AtomicBool>,
+}
+
+/// Stops a running [NotifyLoop] from any thread.
+///
+/// Stopping a loop that isn't running makes its next run return immediately.
+#[derive(Clone)]
+pub struct Stopper {
+    waker: imp::Waker,
+    stopped: Arc<AtomicBool>,
+}
+
+impl NotifyLoop {
+    /// A loop bound to the current thread.
+    pub fn new() -> Self {
+        Self {
+            imp: imp::Loop::new(),
+            stopped: Default::default(),
+        }
+    }
+
+    /// Queue `f` to run on the main queue.
+    ///
+    /// On macOS that is the main dispatch queue, which runs while a [NotifyLoop] runs on the main
+    /// thread. Elsewhere it runs on whichever thread runs a [NotifyLoop].
+    pub fn dispatch(f: impl FnOnce() + Send + 'static) {
+        imp::dispatch_main(Box::new(f))
+    }
+
+    /// A handle stopping this loop.
+    pub fn stopper(&self) -> Stopper {
+        Stopper {
+            waker: self.imp.waker(),
+            stopped: self.stopped.clone(),
+        }
+    }
+
+    /// Run until stopped.
+    pub fn run(&self) {
+        while !self.take_stop() {
+            self.imp.turn(None);
+        }
+    }
+
+    /// Run until stopped or `timeout` elapses.
+    pub fn run_for(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+
+        while !self.take_stop() {
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+
+            self.imp.turn(Some(deadline - now));
+        }
+    }
+
+    /// Run until `predicate` returns `true`, or stopped.
+    ///
+    /// The predicate is evaluated after every wake up of the loop and at least every 50ms. Returns
+    /// `false` if the loop was stopped before the predicate held.
+    pub fn run_until(&self, mut predicate: impl FnMut() -> bool) -> bool {
+        loop {
+            if predicate() {
+                return true;
+            }
+
+            if self.take_stop() {
+                return false;
+            }
+
+            self.imp.turn(Some(POLL_INTERVAL));
+        }
+    }
+
+    fn take_stop(&self) -> bool {
+        self.stopped.swap(false, Ordering::AcqRel)
+    }
+}
+
+impl Default for NotifyLoop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stopper {
+    /// Make the loop return from its current (or next) run.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+impl std::fmt::Debug for NotifyLoop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NotifyLoop").finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for Stopper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stopper")
+            .field("stopped", &self.stopped.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,89 @@
+//! Shared page of per-name generation counters, written by [notifyd](crate::notifyd) on every
+//! post and mapped read-only by clients so check tokens can be polled without a round trip.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::ptr::NonNull;
+use std::sync::atomic::AtomicU64;
+
+/// Number of counters in the page, names beyond this fall back to checking through the socket.
+pub(crate) const SLOTS: u32 = 1024;
+
+/// Slot index telling the client a check token has no counter.
+pub(crate) const NO_SLOT: u32 = u32::MAX;
+
+const LEN: usize = SLOTS as usize * std::mem::size_of::<AtomicU64>();
+
+pub(crate) struct Shm {
+    ptr: NonNull<AtomicU64>,
+}
+
+// The mapping is only ever accessed through atomics.
+unsafe impl Send for Shm {}
+unsafe impl Sync for Shm {}
+
+impl Shm {
+    /// Create (or truncate) the page at `path`, writable by the server and readable by everyone.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        _ = std::fs::remove_file(path);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o644)
+            .open(path)?;
+        file.set_len(LEN as u64)?;
+
+        Self::map(&file, libc::PROT_READ | libc::PROT_WRITE)
+    }
+
+    /// Map an existing page read-only.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() < LEN as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "generation page is too small",
+            ));
+        }
+
+        Self::map(&file, libc::PROT_READ)
+    }
+
+    fn map(file: &File, prot: libc::c_int) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                LEN,
+                prot,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            ptr: NonNull::new(ptr.cast()).unwrap(),
+        })
+    }
+
+    /// The counter of `slot`, which must be below [SLOTS].
+    pub fn slot(&self, slot: u32) -> &AtomicU64 {
+        assert!(slot < SLOTS);
+        unsafe { &*self.ptr.as_ptr().add(slot as usize) }
+    }
+}
+
+impl Drop for Shm {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), LEN) };
+    }
+}
@@ -0,0 +1,147 @@
+use std::ffi::c_int;
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+
+use crate::{NResult, NotifyError};
+
+/// A Unix signal number, delivered by [register_signal](crate::register_signal).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Signal(c_int);
+
+impl Signal {
+    pub const HUP: Self = Self(libc::SIGHUP);
+    pub const INT: Self = Self(libc::SIGINT);
+    pub const ALRM: Self = Self(libc::SIGALRM);
+    pub const TERM: Self = Self(libc::SIGTERM);
+    pub const USR1: Self = Self(libc::SIGUSR1);
+    pub const USR2: Self = Self(libc::SIGUSR2);
+
+    /// Wrap a raw signal number, failing with [NotifyError::InvalidSignal] if the OS rejects it.
+    pub fn new(sig: c_int) -> NResult<Self> {
+        let mut set = unsafe { std::mem::zeroed() };
+
+        match unsafe {
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, sig)
+        } {
+            0 => Ok(Self(sig)),
+            _ => Err(NotifyError::InvalidSignal),
+        }
+    }
+
+    /// The raw signal number.
+    pub fn as_raw(self) -> c_int {
+        self.0
+    }
+}
+
+/// Send `sig` to the current process, the way `notifyd` does for signal registrations.
+pub(crate) fn raise(sig: c_int) {
+    unsafe { libc::kill(libc::getpid(), sig) };
+}
+
+/// Block every signal in the calling thread.
+///
+/// Called by the helper threads of the crate, so signals sent to the process are handled by the
+/// application's own threads.
+pub(crate) fn block_all() {
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigfillset(&mut set);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+    }
+}
+
+/// A `signalfd` receiving one [Signal], so it can be consumed from an event loop instead of a
+/// signal handler.
+///
+/// Creating it blocks the signal in the calling thread. Threads inherit the signal mask they were
+/// spawned with, so create it on the main thread before spawning others, otherwise the signal may
+/// be handled by a thread that doesn't block it.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+/// use darwin_notify::{Signal, SignalFd};
+///
+/// let signals = SignalFd::new(Signal::USR1).unwrap();
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let _subscription =
+///         darwin_notify::register_signal("tech.subcom.darwin-notify", Signal::USR1).unwrap();
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+/// });
+///
+/// assert_eq!(signals.read().unwrap(), Signal::USR1);
+/// ```
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct SignalFd {
+    fd: OwnedFd,
+}
+
+#[cfg(target_os = "linux")]
+impl SignalFd {
+    /// Block `sig` in the calling thread and open a descriptor that becomes readable when it is
+    /// pending.
+    pub fn new(sig: Signal) -> io::Result<Self> {
+        let mut set = unsafe { std::mem::zeroed() };
+
+        let fd = unsafe {
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, sig.0);
+
+            match libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) {
+                0 => {}
+                err => return Err(io::Error::from_raw_os_error(err)),
+            }
+
+            libc::signalfd(-1, &set, libc::SFD_CLOEXEC)
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Wait for the signal and consume it.
+    pub fn read(&self) -> io::Result<Signal> {
+        let mut info: libc::signalfd_siginfo = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<libc::signalfd_siginfo>();
+
+        let read = unsafe { libc::read(self.fd.as_raw_fd(), &mut info as *mut _ as _, size) };
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Signal(info.ssi_signo as c_int))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsFd for SignalFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<SignalFd> for OwnedFd {
+    fn from(signals: SignalFd) -> Self {
+        signals.fd
+    }
+}
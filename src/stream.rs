@@ -0,0 +1,134 @@
+use std::ffi::c_int;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+use crate::{Error, StateChange, Subscription};
+
+/// A notification delivered by a [NotificationStream].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Notification {
+    /// Token of the registration the notification was delivered to.
+    pub token: c_int,
+    /// Position of this delivery in the stream, starting at 1.
+    pub seq: u64,
+}
+
+/// Stream of notifications for a name, see [subscribe].
+///
+/// The registration is cancelled when the stream is dropped.
+#[derive(Debug)]
+pub struct NotificationStream {
+    rx: mpsc::UnboundedReceiver<Notification>,
+    subscription: Subscription,
+}
+
+/// Subscribe to a name as an async [Stream] of [Notification]s.
+///
+/// Requires the `tokio` feature. Works with any [backend](crate::backend), the stream doesn't need
+/// to be polled from the thread callbacks run on.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+/// use tokio_stream::StreamExt;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let mock = Arc::new(MockBackend::new());
+///
+/// let mut stream = backend::with_backend(mock.clone(), || {
+///     let stream = darwin_notify::subscribe("tech.subcom.darwin-notify").unwrap();
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+///     darwin_notify::notify_post("tech.subcom.darwin-notify").unwrap();
+///     stream
+/// });
+///
+/// assert_eq!(stream.next().await.unwrap().seq, 1);
+/// assert_eq!(stream.next().await.unwrap().seq, 2);
+/// # }
+/// ```
+pub fn subscribe(name: &str) -> Result<NotificationStream, Error> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let seq = AtomicU64::new(0);
+
+    let subscription = crate::notify_register(name, move |token| {
+        let seq = seq.fetch_add(1, Ordering::Relaxed) + 1;
+        _ = tx.send(Notification { token, seq });
+    })?;
+
+    Ok(NotificationStream { rx, subscription })
+}
+
+impl NotificationStream {
+    /// The registration backing this stream.
+    pub fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+}
+
+impl Stream for NotificationStream {
+    type Item = Notification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Notification>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Stream of state changes of a name, see [subscribe_state].
+///
+/// The registration is cancelled when the stream is dropped.
+#[derive(Debug)]
+pub struct StateStream {
+    rx: mpsc::UnboundedReceiver<StateChange>,
+    subscription: Subscription,
+}
+
+/// Watch the state of a name as an async [Stream] of [StateChange]s.
+///
+/// Requires the `tokio` feature. Changes are detected like [watch_state](crate::watch_state) does.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+/// use darwin_notify::StateChange;
+/// use tokio_stream::StreamExt;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let mut stream = backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let stream = darwin_notify::subscribe_state("tech.subcom.darwin-notify").unwrap();
+///     darwin_notify::post_with_state("tech.subcom.darwin-notify", 3).unwrap();
+///     stream
+/// });
+///
+/// assert_eq!(stream.next().await, Some(StateChange { old: 0, new: 3 }));
+/// # }
+/// ```
+pub fn subscribe_state(name: &str) -> Result<StateStream, Error> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let subscription = crate::watch_state(name, move |change| _ = tx.send(change))?;
+
+    Ok(StateStream { rx, subscription })
+}
+
+impl StateStream {
+    /// The registration backing this stream.
+    pub fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+}
+
+impl Stream for StateStream {
+    type Item = StateChange;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<StateChange>> {
+        self.rx.poll_recv(cx)
+    }
+}
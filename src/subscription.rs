@@ -0,0 +1,191 @@
+use std::ffi::c_int;
+use std::mem::ManuallyDrop;
+use std::sync::Arc;
+
+use crate::backend::NotifyBackend;
+use crate::token::Live;
+use crate::{Error, NResult, NotifyError, Token};
+
+/// A registration for a name, cancelled when dropped.
+///
+/// Returned by [notify_register](crate::notify_register). Use [Subscription::into_token] or
+/// [Subscription::into_raw] to keep the registration alive past the handle and manage the token
+/// by hand.
+#[must_use = "dropping a Subscription cancels it"]
+pub struct Subscription {
+    token: Token,
+    live: Arc<Live>,
+}
+
+impl Subscription {
+    pub(crate) fn new(token: c_int, backend: Arc<dyn NotifyBackend>) -> Self {
+        let (token, live) = Token::track(token, backend);
+        Self { token, live }
+    }
+
+    /// The token of this registration.
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    /// Suspend delivery of notifications.
+    pub fn suspend(&self) -> Result<(), Error> {
+        self.with_raw(|backend, raw| backend.suspend(raw))
+    }
+
+    /// Removes one level of suspension.
+    pub fn resume(&self) -> Result<(), Error> {
+        self.with_raw(|backend, raw| backend.resume(raw))
+    }
+
+    /// Check if any notifications have been posted since the last check.
+    ///
+    /// With [DaemonBackend](crate::backend::DaemonBackend) this is a load from memory the server
+    /// shares, without taking a lock.
+    pub fn check(&self) -> Result<bool, Error> {
+        let poller = self.live.poller.as_ref();
+        self.with_raw(
+            |backend, raw| match poller.and_then(|poller| poller.poll()) {
+                Some(posted) => Ok(posted),
+                None => backend.check(raw),
+            },
+        )
+    }
+
+    /// Get the 64-bit state value of the name.
+    pub fn state(&self) -> Result<u64, Error> {
+        self.with_raw(|backend, raw| backend.get_state(raw))
+    }
+
+    /// Set the 64-bit state value of the name.
+    pub fn set_state(&self, state: u64) -> Result<(), Error> {
+        self.with_raw(|backend, raw| backend.set_state(raw, state))
+    }
+
+    /// Cancel the registration, reporting errors dropping would ignore.
+    pub fn cancel(self) -> Result<(), Error> {
+        let (token, live) = self.release();
+
+        let raw = token.as_raw();
+        if live.is_cancelled() {
+            return Err(NotifyError::InvalidToken.with_token(raw));
+        }
+
+        live.backend
+            .cancel(raw)
+            .map_err(|err| err.with_token(raw))?;
+        token.forget();
+        Ok(())
+    }
+
+    /// Release the token without cancelling it.
+    ///
+    /// The registration stays alive until the token is passed to
+    /// [notify_cancel](crate::notify_cancel), which also lets go of the backend.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::Arc;
+    /// use darwin_notify::backend::{self, MockBackend};
+    ///
+    /// let mock = Arc::new(MockBackend::new());
+    /// backend::with_backend(mock.clone(), || {
+    ///     let token = darwin_notify::register_check("tech.subcom.darwin-notify")
+    ///         .unwrap()
+    ///         .into_token();
+    ///     darwin_notify::notify_cancel(token).unwrap();
+    /// });
+    /// assert_eq!(Arc::strong_count(&mock), 1);
+    /// ```
+    pub fn into_token(self) -> Token {
+        self.release().0
+    }
+
+    /// Release the raw token without cancelling it, for code that still needs the integer.
+    ///
+    /// The token is no longer tracked, copies of [token](Subscription::token) become invalid.
+    /// Cancel the registration through [Token::from_raw], while the backend it was made with is
+    /// still current, or for a process-local name from anywhere.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::Arc;
+    /// use darwin_notify::backend::{self, MockBackend};
+    /// use darwin_notify::Token;
+    ///
+    /// backend::with_backend(Arc::new(MockBackend::new()), || {
+    ///     let raw = darwin_notify::register_check("tech.subcom.darwin-notify")
+    ///         .unwrap()
+    ///         .into_raw();
+    ///
+    ///     let token = unsafe { Token::from_raw(raw) };
+    ///     assert!(token.is_valid());
+    ///     darwin_notify::notify_cancel(token).unwrap();
+    ///     assert!(!token.is_valid());
+    /// });
+    ///
+    /// // Tokens of process-local names never reach another backend.
+    /// let raw = darwin_notify::register_check("self.tech.subcom.darwin-notify")
+    ///     .unwrap()
+    ///     .into_raw();
+    /// backend::with_backend(Arc::new(MockBackend::new()), || {
+    ///     let mock = darwin_notify::register_check("tech.subcom.darwin-notify").unwrap();
+    ///     darwin_notify::notify_cancel(unsafe { Token::from_raw(raw) }).unwrap();
+    ///     assert!(mock.check().is_ok());
+    /// });
+    /// ```
+    pub fn into_raw(self) -> c_int {
+        let token = self.into_token();
+        token.forget();
+        token.as_raw()
+    }
+
+    /// Take the token apart without cancelling it.
+    fn release(self) -> (Token, Arc<Live>) {
+        // Drop would cancel the registration, the fields are moved out instead.
+        let this = ManuallyDrop::new(self);
+        (this.token, unsafe { std::ptr::read(&this.live) })
+    }
+
+    /// Call `f` with the raw token, attaching it to errors.
+    ///
+    /// Only reads the cancelled flag shared with the token, polling takes no lock here.
+    fn with_raw<T>(
+        &self,
+        f: impl FnOnce(&dyn NotifyBackend, c_int) -> NResult<T>,
+    ) -> Result<T, Error> {
+        let raw = self.token.as_raw();
+        if self.live.is_cancelled() {
+            return Err(NotifyError::InvalidToken.with_token(raw));
+        }
+
+        f(&*self.live.backend, raw).map_err(|err| err.with_token(raw))
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Already cancelled with notify_cancel.
+        if self.live.is_cancelled() {
+            return;
+        }
+
+        let token = self.token.as_raw();
+        self.token.forget();
+        if let Err(err) = self.live.backend.cancel(token) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("darwin-notify: failed to cancel token {token}: {err}");
+
+            // just for the lints
+            _ = err;
+        }
+    }
+}
+
+impl std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
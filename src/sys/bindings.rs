@@ -0,0 +1,125 @@
+// Bindings for notify.h, maintained by hand and checked against the ABI by src/sys/tests.rs.
+// `cargo build --features regenerate-bindings` on macOS overwrites this file with bindgen's output
+// to compare with, port the changes instead of committing it.
+
+pub const NOTIFY_STATUS_OK: u32 = 0;
+pub const NOTIFY_STATUS_INVALID_NAME: u32 = 1;
+pub const NOTIFY_STATUS_INVALID_TOKEN: u32 = 2;
+pub const NOTIFY_STATUS_INVALID_PORT: u32 = 3;
+pub const NOTIFY_STATUS_INVALID_FILE: u32 = 4;
+pub const NOTIFY_STATUS_INVALID_SIGNAL: u32 = 5;
+pub const NOTIFY_STATUS_INVALID_REQUEST: u32 = 6;
+pub const NOTIFY_STATUS_NOT_AUTHORIZED: u32 = 7;
+pub const NOTIFY_STATUS_OPT_DISABLE: u32 = 8;
+pub const NOTIFY_STATUS_SERVER_NOT_FOUND: u32 = 9;
+pub const NOTIFY_STATUS_NULL_INPUT: u32 = 10;
+pub const NOTIFY_STATUS_FAILED: u32 = 1000000;
+pub const NOTIFY_REUSE: u32 = 1;
+pub const NOTIFY_TOKEN_INVALID: i32 = -1;
+pub const QOS_CLASS_USER_INTERACTIVE: u32 = 33;
+pub const QOS_CLASS_USER_INITIATED: u32 = 25;
+pub const QOS_CLASS_DEFAULT: u32 = 21;
+pub const QOS_CLASS_UTILITY: u32 = 17;
+pub const QOS_CLASS_BACKGROUND: u32 = 9;
+pub type qos_class_t = ::std::os::raw::c_uint;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct dispatch_queue_s {
+    _unused: [u8; 0],
+}
+pub type dispatch_queue_t = *mut dispatch_queue_s;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct dispatch_queue_global_s {
+    _unused: [u8; 0],
+}
+pub type dispatch_queue_global_t = *mut dispatch_queue_global_s;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct dispatch_queue_attr_s {
+    _unused: [u8; 0],
+}
+pub type dispatch_queue_attr_t = *mut dispatch_queue_attr_s;
+pub type dispatch_object_t = *mut ::std::os::raw::c_void;
+pub type dispatch_function_t =
+    ::std::option::Option<unsafe extern "C" fn(arg1: *mut ::std::os::raw::c_void)>;
+pub type notify_handler_t = *mut ::std::os::raw::c_void;
+extern "C" {
+    pub fn notify_post(name: *const ::std::os::raw::c_char) -> u32;
+    pub fn notify_register_dispatch(
+        name: *const ::std::os::raw::c_char,
+        out_token: *mut ::std::os::raw::c_int,
+        queue: dispatch_queue_t,
+        handler: notify_handler_t,
+    ) -> u32;
+    pub fn notify_register_check(
+        name: *const ::std::os::raw::c_char,
+        out_token: *mut ::std::os::raw::c_int,
+    ) -> u32;
+    pub fn notify_register_signal(
+        name: *const ::std::os::raw::c_char,
+        sig: ::std::os::raw::c_int,
+        out_token: *mut ::std::os::raw::c_int,
+    ) -> u32;
+    pub fn notify_register_file_descriptor(
+        name: *const ::std::os::raw::c_char,
+        notify_fd: *mut ::std::os::raw::c_int,
+        flags: ::std::os::raw::c_int,
+        out_token: *mut ::std::os::raw::c_int,
+    ) -> u32;
+    pub fn notify_check(token: ::std::os::raw::c_int, check: *mut ::std::os::raw::c_int) -> u32;
+    pub fn notify_cancel(token: ::std::os::raw::c_int) -> u32;
+    pub fn notify_suspend(token: ::std::os::raw::c_int) -> u32;
+    pub fn notify_resume(token: ::std::os::raw::c_int) -> u32;
+    pub fn notify_set_state(token: ::std::os::raw::c_int, state64: u64) -> u32;
+    pub fn notify_get_state(token: ::std::os::raw::c_int, state64: *mut u64) -> u32;
+    pub fn notify_is_valid_token(val: ::std::os::raw::c_int) -> bool;
+    pub fn dispatch_get_current_queue() -> dispatch_queue_t;
+    pub fn dispatch_get_global_queue(identifier: isize, flags: usize) -> dispatch_queue_global_t;
+    pub fn dispatch_queue_create(
+        label: *const ::std::os::raw::c_char,
+        attr: dispatch_queue_attr_t,
+    ) -> dispatch_queue_t;
+    pub fn dispatch_queue_attr_make_with_qos_class(
+        attr: dispatch_queue_attr_t,
+        qos_class: qos_class_t,
+        relative_priority: ::std::os::raw::c_int,
+    ) -> dispatch_queue_attr_t;
+    pub fn dispatch_release(object: dispatch_object_t);
+    pub fn dispatch_async_f(
+        queue: dispatch_queue_t,
+        context: *mut ::std::os::raw::c_void,
+        work: dispatch_function_t,
+    );
+    pub static mut _dispatch_main_q: dispatch_queue_s;
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct __CFRunLoop {
+    _unused: [u8; 0],
+}
+pub type CFRunLoopRef = *mut __CFRunLoop;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct __CFString {
+    _unused: [u8; 0],
+}
+pub type CFStringRef = *const __CFString;
+pub type CFTimeInterval = f64;
+pub type Boolean = ::std::os::raw::c_uchar;
+extern "C" {
+    pub fn CFRunLoopRun();
+    pub fn CFRunLoopGetCurrent() -> CFRunLoopRef;
+    pub fn CFRunLoopStop(rl: CFRunLoopRef);
+    pub fn CFRunLoopWakeUp(rl: CFRunLoopRef);
+    pub fn CFRunLoopRunInMode(
+        mode: CFStringRef,
+        seconds: CFTimeInterval,
+        returnAfterSourceHandled: Boolean,
+    ) -> ::std::os::raw::c_int;
+    pub static kCFRunLoopDefaultMode: CFStringRef;
+}
+pub const kCFRunLoopRunFinished: u32 = 1;
+pub const kCFRunLoopRunStopped: u32 = 2;
+pub const kCFRunLoopRunTimedOut: u32 = 3;
+pub const kCFRunLoopRunHandledSource: u32 = 4;
@@ -0,0 +1,30 @@
+//! Raw bindings to `libnotify`, `libdispatch` and the parts of `CoreFoundation` the crate uses.
+//!
+//! The bindings are maintained by hand and checked in, so building the crate needs neither
+//! libclang nor the macOS SDK, and the tests check them against the expected ABI. The
+//! `regenerate-bindings` feature overwrites them with bindgen's output for `notify.h` on macOS, to
+//! compare with the installed SDK. Inline functions like `dispatch_get_main_queue` aren't
+//! exported, they are written in this module.
+
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(unused)]
+#![allow(clippy::all)]
+
+mod bindings;
+#[cfg(test)]
+mod tests;
+
+pub use bindings::*;
+
+/// The main dispatch queue. `dispatch_get_main_queue` is inline in the SDK and not exported, it
+/// returns the address of `_dispatch_main_q`.
+#[cfg(target_os = "macos")]
+pub fn main_queue() -> dispatch_queue_t {
+    unsafe { std::ptr::addr_of_mut!(_dispatch_main_q) }
+}
+
+// libnotify and libdispatch are part of libSystem, linked by default.
+#[cfg_attr(target_os = "macos", link(name = "CoreFoundation", kind = "framework"))]
+extern "C" {}
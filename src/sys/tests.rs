@@ -0,0 +1,131 @@
+//! The bindings against the ABI documented in `notify.h`, checked without linking so they run on
+//! any host.
+
+use std::ffi::{c_char, c_int, c_uchar, c_uint, c_void};
+use std::mem::{align_of, size_of};
+
+use super::*;
+use crate::{NotifyError, Qos};
+
+// A mismatch in the signature of a binding fails to compile. The constants are never used, so
+// the symbols are never referenced.
+const _: unsafe extern "C" fn(*const c_char) -> u32 = notify_post;
+const _: unsafe extern "C" fn(
+    *const c_char,
+    *mut c_int,
+    dispatch_queue_t,
+    notify_handler_t,
+) -> u32 = notify_register_dispatch;
+const _: unsafe extern "C" fn(*const c_char, *mut c_int) -> u32 = notify_register_check;
+const _: unsafe extern "C" fn(*const c_char, c_int, *mut c_int) -> u32 = notify_register_signal;
+const _: unsafe extern "C" fn(*const c_char, *mut c_int, c_int, *mut c_int) -> u32 =
+    notify_register_file_descriptor;
+const _: unsafe extern "C" fn(c_int, *mut c_int) -> u32 = notify_check;
+const _: unsafe extern "C" fn(c_int) -> u32 = notify_cancel;
+const _: unsafe extern "C" fn(c_int) -> u32 = notify_suspend;
+const _: unsafe extern "C" fn(c_int) -> u32 = notify_resume;
+const _: unsafe extern "C" fn(c_int, u64) -> u32 = notify_set_state;
+const _: unsafe extern "C" fn(c_int, *mut u64) -> u32 = notify_get_state;
+const _: unsafe extern "C" fn(c_int) -> bool = notify_is_valid_token;
+
+const _: unsafe extern "C" fn() -> dispatch_queue_t = dispatch_get_current_queue;
+const _: unsafe extern "C" fn(isize, usize) -> dispatch_queue_global_t = dispatch_get_global_queue;
+const _: unsafe extern "C" fn(*const c_char, dispatch_queue_attr_t) -> dispatch_queue_t =
+    dispatch_queue_create;
+const _: unsafe extern "C" fn(dispatch_queue_attr_t, qos_class_t, c_int) -> dispatch_queue_attr_t =
+    dispatch_queue_attr_make_with_qos_class;
+const _: unsafe extern "C" fn(dispatch_object_t) = dispatch_release;
+const _: unsafe extern "C" fn(dispatch_queue_t, *mut c_void, dispatch_function_t) =
+    dispatch_async_f;
+
+const _: unsafe extern "C" fn() = CFRunLoopRun;
+const _: unsafe extern "C" fn() -> CFRunLoopRef = CFRunLoopGetCurrent;
+const _: unsafe extern "C" fn(CFRunLoopRef) = CFRunLoopStop;
+const _: unsafe extern "C" fn(CFRunLoopRef) = CFRunLoopWakeUp;
+const _: unsafe extern "C" fn(CFStringRef, CFTimeInterval, Boolean) -> c_int = CFRunLoopRunInMode;
+
+#[test]
+fn status_constants() {
+    assert_eq!(NOTIFY_STATUS_OK, 0);
+    assert_eq!(NOTIFY_STATUS_INVALID_NAME, 1);
+    assert_eq!(NOTIFY_STATUS_INVALID_TOKEN, 2);
+    assert_eq!(NOTIFY_STATUS_INVALID_PORT, 3);
+    assert_eq!(NOTIFY_STATUS_INVALID_FILE, 4);
+    assert_eq!(NOTIFY_STATUS_INVALID_SIGNAL, 5);
+    assert_eq!(NOTIFY_STATUS_INVALID_REQUEST, 6);
+    assert_eq!(NOTIFY_STATUS_NOT_AUTHORIZED, 7);
+    assert_eq!(NOTIFY_STATUS_OPT_DISABLE, 8);
+    assert_eq!(NOTIFY_STATUS_SERVER_NOT_FOUND, 9);
+    assert_eq!(NOTIFY_STATUS_NULL_INPUT, 10);
+    assert_eq!(NOTIFY_STATUS_FAILED, 1000000);
+}
+
+#[test]
+fn errors_match_status() {
+    let statuses = [
+        (NOTIFY_STATUS_INVALID_NAME, NotifyError::InvalidName),
+        (NOTIFY_STATUS_INVALID_TOKEN, NotifyError::InvalidToken),
+        (NOTIFY_STATUS_INVALID_PORT, NotifyError::InvalidPort),
+        (NOTIFY_STATUS_INVALID_FILE, NotifyError::InvalidFile),
+        (NOTIFY_STATUS_INVALID_SIGNAL, NotifyError::InvalidSignal),
+        (NOTIFY_STATUS_INVALID_REQUEST, NotifyError::InvalidRequest),
+        (NOTIFY_STATUS_NOT_AUTHORIZED, NotifyError::NotAuthorized),
+        (NOTIFY_STATUS_OPT_DISABLE, NotifyError::OptDisabled),
+        (NOTIFY_STATUS_SERVER_NOT_FOUND, NotifyError::ServerNotFound),
+        (NOTIFY_STATUS_NULL_INPUT, NotifyError::NullInput),
+        (NOTIFY_STATUS_FAILED, NotifyError::Failed),
+    ];
+
+    for (status, err) in statuses {
+        assert_eq!(err.code(), status);
+        assert_eq!(NotifyError::from_u32(status), err);
+    }
+}
+
+#[test]
+fn token_constants() {
+    assert_eq!(NOTIFY_REUSE, 0x1);
+    assert_eq!(NOTIFY_TOKEN_INVALID, -1);
+}
+
+fn assert_pointer<T>() {
+    assert_eq!(size_of::<T>(), size_of::<*const c_void>());
+    assert_eq!(align_of::<T>(), align_of::<*const c_void>());
+}
+
+#[test]
+fn type_layout() {
+    assert_pointer::<dispatch_queue_t>();
+    assert_pointer::<dispatch_object_t>();
+    assert_pointer::<notify_handler_t>();
+    assert_pointer::<dispatch_function_t>();
+    assert_pointer::<CFRunLoopRef>();
+    assert_pointer::<CFStringRef>();
+
+    assert_eq!(size_of::<qos_class_t>(), size_of::<c_uint>());
+    assert_eq!(size_of::<CFTimeInterval>(), 8);
+    assert_eq!(size_of::<Boolean>(), size_of::<c_uchar>());
+}
+
+#[test]
+fn qos_constants() {
+    assert_eq!(QOS_CLASS_USER_INTERACTIVE, 0x21);
+    assert_eq!(QOS_CLASS_USER_INITIATED, 0x19);
+    assert_eq!(QOS_CLASS_DEFAULT, 0x15);
+    assert_eq!(QOS_CLASS_UTILITY, 0x11);
+    assert_eq!(QOS_CLASS_BACKGROUND, 0x09);
+
+    assert_eq!(Qos::UserInteractive as u32, QOS_CLASS_USER_INTERACTIVE);
+    assert_eq!(Qos::UserInitiated as u32, QOS_CLASS_USER_INITIATED);
+    assert_eq!(Qos::Default as u32, QOS_CLASS_DEFAULT);
+    assert_eq!(Qos::Utility as u32, QOS_CLASS_UTILITY);
+    assert_eq!(Qos::Background as u32, QOS_CLASS_BACKGROUND);
+}
+
+#[test]
+fn run_loop_constants() {
+    assert_eq!(kCFRunLoopRunFinished, 1);
+    assert_eq!(kCFRunLoopRunStopped, 2);
+    assert_eq!(kCFRunLoopRunTimedOut, 3);
+    assert_eq!(kCFRunLoopRunHandledSource, 4);
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::ffi::c_int;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::backend::{self, CheckPoller, NotifyBackend};
+use crate::{Error, NotifyError};
+
+/// Generation of tokens made with [Token::from_raw], which are never checked on the Rust side.
+const UNTRACKED: u64 = 0;
+
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(UNTRACKED + 1);
+/// Every live token, by generation.
+static LIVE: RwLock<BTreeMap<u64, Arc<Live>>> = RwLock::new(BTreeMap::new());
+
+/// A tracked token, shared with its [Subscription](crate::Subscription) so using it there takes
+/// no lock.
+pub(crate) struct Live {
+    pub backend: Arc<dyn NotifyBackend>,
+    /// Checks the token without a call into the backend, when it can.
+    pub poller: Option<Arc<dyn CheckPoller>>,
+    cancelled: AtomicBool,
+}
+
+impl Live {
+    /// Whether the token was cancelled through this crate.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// A registration token.
+///
+/// Tokens handed out by this crate carry a generation tag that lives until the token is
+/// cancelled. Using a token after that fails with [NotifyError::InvalidToken] before reaching the
+/// backend, even if the backend has given the same raw value to a newer registration. The free
+/// functions taking a token use the backend it was registered with.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use darwin_notify::backend::{self, MockBackend};
+/// use darwin_notify::NotifyError;
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let token = darwin_notify::register_check("tech.subcom.darwin-notify")
+///         .unwrap()
+///         .into_token();
+///     assert!(token.is_valid());
+///
+///     darwin_notify::notify_cancel(token).unwrap();
+///     assert!(!token.is_valid());
+///     assert_eq!(darwin_notify::notify_check(token).unwrap_err(), NotifyError::InvalidToken);
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token {
+    raw: c_int,
+    generation: u64,
+}
+
+impl Token {
+    /// Wrap a token obtained outside this crate, for example through the `sys` module or
+    /// [Subscription::into_raw](crate::Subscription::into_raw).
+    ///
+    /// Negative values are tokens of process-local names and go to the process wide
+    /// [LocalBackend](backend::LocalBackend), other values to the [current](backend::current)
+    /// backend.
+    ///
+    /// # Safety
+    /// `raw` must be a token registered for a process-local name, or with the current backend.
+    /// Such a token carries no generation, so using it after it is cancelled is only caught by
+    /// the backend, which may have handed the same value to another registration.
+    pub unsafe fn from_raw(raw: c_int) -> Self {
+        Self {
+            raw,
+            generation: UNTRACKED,
+        }
+    }
+
+    /// The raw token the backend knows.
+    pub fn as_raw(self) -> c_int {
+        self.raw
+    }
+
+    /// Whether the token is still registered, uses `notify_is_valid_token` on Darwin.
+    pub fn is_valid(self) -> bool {
+        match self.resolve() {
+            Ok((raw, backend)) => backend.is_valid(raw),
+            Err(_) => false,
+        }
+    }
+
+    /// Start tracking a token `backend` just registered.
+    pub(crate) fn track(raw: c_int, backend: Arc<dyn NotifyBackend>) -> (Self, Arc<Live>) {
+        let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        let live = Arc::new(Live {
+            poller: backend.check_poller(raw),
+            backend,
+            cancelled: AtomicBool::new(false),
+        });
+        LIVE.write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(generation, live.clone());
+
+        (Self { raw, generation }, live)
+    }
+
+    /// The raw token and the backend it belongs to, unless it was cancelled through this crate.
+    pub(crate) fn resolve(self) -> Result<(c_int, Arc<dyn NotifyBackend>), Error> {
+        if self.generation == UNTRACKED {
+            let backend = match self.raw < 0 {
+                true => backend::process_local(),
+                false => backend::current(),
+            };
+            return Ok((self.raw, backend));
+        }
+
+        LIVE.read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&self.generation)
+            .map(|live| (self.raw, live.backend.clone()))
+            .ok_or_else(|| NotifyError::InvalidToken.with_token(self.raw))
+    }
+
+    /// Stop tracking a token, which was cancelled or handed out as a raw integer.
+    pub(crate) fn forget(self) {
+        let live = LIVE
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.generation);
+
+        if let Some(live) = live {
+            live.cancelled.store(true, Ordering::Release);
+        }
+    }
+}
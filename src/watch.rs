@@ -0,0 +1,92 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{backend, panic, Error, NotificationName, Subscription};
+
+/// A change of the state of a name, see [watch_state].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateChange {
+    /// State when the previous change was reported, or when watching started.
+    pub old: u64,
+    /// State the name was posted with.
+    pub new: u64,
+}
+
+struct Watch<F> {
+    /// `None` until the state at registration time is known.
+    last: Option<u64>,
+    cb: F,
+}
+
+/// Call `cb` with the old and new state every time a name is posted with a different state.
+///
+/// Posts that leave the state unchanged are skipped. With [backend::DaemonBackend] the server
+/// captures the state when the name is posted, so every change is reported with the value it
+/// was posted with. On Darwin the state is read when the notification is delivered and a change
+/// quickly followed by another one may be reported once, with the latest value.
+///
+/// Like [notify_register_mut](crate::notify_register_mut) the callback never runs concurrently
+/// with itself. The registration is cancelled when the returned [Subscription] is dropped.
+///
+/// # Example
+/// ```
+/// use std::sync::{mpsc, Arc};
+/// use darwin_notify::backend::{self, MockBackend};
+/// use darwin_notify::StateChange;
+///
+/// backend::with_backend(Arc::new(MockBackend::new()), || {
+///     let (tx, rx) = mpsc::channel();
+///     let _subscription = darwin_notify::watch_state("tech.subcom.darwin-notify", move |change| {
+///         tx.send(change).unwrap();
+///     })
+///     .unwrap();
+///
+///     darwin_notify::post_with_state("tech.subcom.darwin-notify", 1).unwrap();
+///     darwin_notify::post_with_state("tech.subcom.darwin-notify", 1).unwrap();
+///     darwin_notify::post_with_state("tech.subcom.darwin-notify", 2).unwrap();
+///
+///     assert_eq!(
+///         rx.try_iter().collect::<Vec<_>>(),
+///         [StateChange { old: 0, new: 1 }, StateChange { old: 1, new: 2 }]
+///     );
+/// });
+/// ```
+pub fn watch_state<F>(name: &str, cb: F) -> Result<Subscription, Error>
+where
+    F: FnMut(StateChange) + Send + 'static,
+{
+    NotificationName::validate(name)?;
+
+    let watch = Arc::new(Mutex::new(Watch { last: None, cb }));
+    let deliver = watch.clone();
+
+    let backend = backend::for_name(name);
+    let slot = Arc::new(panic::TokenSlot::default());
+    let cb = panic::guard_state(
+        name,
+        None,
+        slot.clone(),
+        Box::new(move |_, new| {
+            let mut watch = deliver.lock().unwrap_or_else(|e| e.into_inner());
+
+            // Delivered before the initial state was read, take it as the starting point.
+            if let Some(old) = watch.last.replace(new).filter(|old| *old != new) {
+                (watch.cb)(StateChange { old, new })
+            }
+        }),
+    );
+    let token = backend
+        .clone()
+        .register_state(name, cb)
+        .map_err(|err| err.with_name(name))?;
+    let subscription = Subscription::new(token, backend);
+    slot.set(subscription.token());
+
+    let state = subscription.state()?;
+    watch
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .last
+        .get_or_insert(state);
+
+    Ok(subscription)
+}